@@ -0,0 +1,137 @@
+use std::io::{self, Write};
+use crate::impg::{AlignmentStats, Impg, ProjectedInterval};
+use crate::paf::Strand;
+
+/// An alignment through which a result was projected from its parent result
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosureEdge {
+    /// Indexes of the parent and child results
+    pub from: usize,
+    pub to: usize,
+    /// 0-based index of the PAF record
+    pub alignment_id: usize,
+    /// Strand of the child range relative to the parent range
+    pub strand: Strand,
+    /// Identity of the alignment between both ranges
+    pub identity: f64,
+    /// Hop depth of the child range
+    pub hop: usize,
+}
+
+/// Edges of the graph of a (transitive) projection, from the provenance of its results
+pub fn closure_edges(results: &[ProjectedInterval]) -> Vec<ClosureEdge> {
+    results.iter().enumerate().filter_map(|(i, result)| {
+        let provenance = &result.provenance;
+        let (parent, alignment_id) = (provenance.parent?, provenance.alignment_id?);
+        let strand = if result.strand == results[parent].strand { Strand::Forward } else { Strand::Reverse };
+        Some(ClosureEdge {
+            from: parent,
+            to: i,
+            alignment_id,
+            strand,
+            identity: AlignmentStats::from_cigar(&provenance.hop_cigar).identity(),
+            hop: provenance.depth,
+        })
+    }).collect()
+}
+
+fn strand_char(strand: Strand) -> char {
+    match strand {
+        Strand::Forward => '+',
+        Strand::Reverse => '-',
+    }
+}
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Write the projection graph in DOT: one node per result range, one edge per alignment
+pub fn write_dot<W: Write>(writer: &mut W, impg: &Impg, region: &str, results: &[ProjectedInterval]) -> io::Result<()> {
+    writeln!(writer, "digraph \"{}\" {{", escape(region))?;
+    writeln!(writer, "  node [shape=box];")?;
+    for (i, result) in results.iter().enumerate() {
+        writeln!(
+            writer,
+            "  n{} [label=\"{}:{}-{}({})\\nhop {}\"];",
+            i, escape(impg.seq_index.get_name(result.query_id).unwrap()), result.query_start, result.query_end,
+            strand_char(result.strand), result.provenance.depth
+        )?;
+    }
+    for edge in closure_edges(results) {
+        writeln!(
+            writer,
+            "  n{} -> n{} [label=\"{} id={:.4}\", alignment_id={}, strand=\"{}\", identity={:.6}, hop={}];",
+            edge.from, edge.to, strand_char(edge.strand), edge.identity,
+            edge.alignment_id, strand_char(edge.strand), edge.identity, edge.hop
+        )?;
+    }
+    writeln!(writer, "}}")
+}
+
+/// Write the projection graph as a JSON object with the queried `region` and `nodes` and `edges` arrays
+pub fn write_json<W: Write>(writer: &mut W, impg: &Impg, region: &str, results: &[ProjectedInterval]) -> io::Result<()> {
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  \"region\": \"{}\",", escape(region))?;
+    writeln!(writer, "  \"nodes\": [")?;
+    for (i, result) in results.iter().enumerate() {
+        writeln!(
+            writer,
+            "    {{\"id\": {}, \"seq\": \"{}\", \"start\": {}, \"end\": {}, \"strand\": \"{}\", \"hop\": {}}}{}",
+            i, escape(impg.seq_index.get_name(result.query_id).unwrap()), result.query_start, result.query_end,
+            strand_char(result.strand), result.provenance.depth, if i + 1 < results.len() { "," } else { "" }
+        )?;
+    }
+    writeln!(writer, "  ],")?;
+    writeln!(writer, "  \"edges\": [")?;
+    let edges = closure_edges(results);
+    for (i, edge) in edges.iter().enumerate() {
+        writeln!(
+            writer,
+            "    {{\"from\": {}, \"to\": {}, \"alignment_id\": {}, \"strand\": \"{}\", \"identity\": {:.6}, \"hop\": {}}}{}",
+            edge.from, edge.to, edge.alignment_id, strand_char(edge.strand), edge.identity, edge.hop,
+            if i + 1 < edges.len() { "," } else { "" }
+        )?;
+    }
+    writeln!(writer, "  ]")?;
+    writeln!(writer, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impg::{CigarOp, Provenance};
+
+    fn result(strand: Strand, alignment_id: Option<usize>, parent: Option<usize>, depth: usize, hop_cigar: &[(i32, char)]) -> ProjectedInterval {
+        ProjectedInterval {
+            strand,
+            provenance: Provenance {
+                alignment_id,
+                parent,
+                depth,
+                hop_cigar: hop_cigar.iter().map(|&(len, op)| CigarOp::new(len, op)).collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_closure_edges() {
+        let results = vec![
+            result(Strand::Forward, None, None, 0, &[(10, '=')]),
+            result(Strand::Reverse, Some(3), Some(0), 1, &[(9, '='), (1, 'X')]),
+            result(Strand::Reverse, Some(5), Some(1), 2, &[(10, '=')]),
+        ];
+        let edges = closure_edges(&results);
+        assert_eq!(edges, vec![
+            ClosureEdge { from: 0, to: 1, alignment_id: 3, strand: Strand::Reverse, identity: 0.9, hop: 1 },
+            ClosureEdge { from: 1, to: 2, alignment_id: 5, strand: Strand::Forward, identity: 1.0, hop: 2 },
+        ]);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
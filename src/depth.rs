@@ -0,0 +1,275 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use crate::impg::{Impg, ProjectedInterval};
+use crate::seqidx::pansn_sample;
+
+/// Groups present over `[start, end)`, where each group is a set of ranges.
+/// Returns runs of constant presence as `(start, end, present group indexes)`, covering the whole range.
+pub fn presence_runs(groups: &[Vec<(i32, i32)>], start: i32, end: i32) -> Vec<(i32, i32, Vec<usize>)> {
+    let mut events: Vec<(i32, bool, usize)> = Vec::new();
+    for (group, ranges) in groups.iter().enumerate() {
+        for &(s, e) in ranges {
+            let (s, e) = (s.max(start), e.min(end));
+            if s < e {
+                // Ends sort before starts at the same position
+                events.push((s, true, group));
+                events.push((e, false, group));
+            }
+        }
+    }
+    events.sort_unstable();
+
+    let mut runs: Vec<(i32, i32, Vec<usize>)> = Vec::new();
+    let mut position = start;
+    // Number of overlapping ranges of each present group
+    let mut present: HashMap<usize, usize> = HashMap::new();
+    let mut push = |runs: &mut Vec<(i32, i32, Vec<usize>)>, run_end: i32, present: &HashMap<usize, usize>| {
+        if position < run_end {
+            let groups: Vec<usize> = present.keys().copied().collect::<BTreeSet<_>>().into_iter().collect();
+            match runs.last_mut() {
+                Some(last) if last.2 == groups => last.1 = run_end,
+                _ => runs.push((position, run_end, groups)),
+            }
+            position = run_end;
+        }
+    };
+    for (event_position, is_start, group) in events {
+        push(&mut runs, event_position, &present);
+        if is_start {
+            *present.entry(group).or_default() += 1;
+        } else if let Some(count) = present.get_mut(&group) {
+            *count -= 1;
+            if *count == 0 {
+                present.remove(&group);
+            }
+        }
+    }
+    push(&mut runs, end, &present);
+    runs
+}
+
+/// Depth over `[start, end)` of groups of ranges, where each group counts at most once per position.
+/// Returns runs of constant depth as `(start, end, depth)`, covering the whole range.
+pub fn depth_runs(groups: &[Vec<(i32, i32)>], start: i32, end: i32) -> Vec<(i32, i32, usize)> {
+    let mut runs: Vec<(i32, i32, usize)> = Vec::new();
+    for (run_start, run_end, present) in presence_runs(groups, start, end) {
+        match runs.last_mut() {
+            Some(last) if last.2 == present.len() => last.1 = run_end,
+            _ => runs.push((run_start, run_end, present.len())),
+        }
+    }
+    runs
+}
+
+/// Aligned target ranges of the results (deletions excluded), grouped by query sequence
+/// (or by PanSN sample with `by_sample`)
+fn aligned_ranges_by<'a>(impg: &'a Impg, results: &[ProjectedInterval], by_sample: bool) -> HashMap<&'a str, Vec<(i32, i32)>> {
+    let mut groups: HashMap<&str, Vec<(i32, i32)>> = HashMap::new();
+    for result in results {
+        let query_name = impg.seq_index.get_name(result.query_id).unwrap();
+        let key = if by_sample { pansn_sample(query_name) } else { query_name };
+        groups.entry(key).or_default().extend(result.aligned_target_ranges());
+    }
+    groups
+}
+
+/// Per-base depth of the results over the target range `[start, end)`: the number of distinct
+/// query sequences (or PanSN samples with `by_sample`) with an aligned base at each position.
+/// Deletions in the alignments and the input range itself do not count.
+pub fn target_depth(impg: &Impg, results: &[ProjectedInterval], start: i32, end: i32, by_sample: bool) -> Vec<(i32, i32, usize)> {
+    let projections: Vec<ProjectedInterval> = results.iter()
+        .filter(|result| result.provenance.alignment_id.is_some())
+        .cloned()
+        .collect();
+    let groups: Vec<Vec<(i32, i32)>> = aligned_ranges_by(impg, &projections, by_sample).into_values().collect();
+    depth_runs(&groups, start, end)
+}
+
+/// Copies of a query sequence or PanSN sample over a target range
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopyNumber {
+    /// Distinct copies: non-overlapping, non-adjacent ranges of the query sequences
+    pub copies: usize,
+    /// Fraction of the target range aligned to at least one copy
+    pub covered_fraction: f64,
+    /// Aligned target bases of all the copies over the length of the target range
+    pub mean_copy_number: f64,
+}
+
+/// Number of distinct copies among `(seq_id, start, end)` query ranges, overlapping or adjacent ranges
+/// of the same sequence (e.g. the pieces of a split projection) making a single copy
+fn count_copies(mut ranges: Vec<(u32, i32, i32)>) -> usize {
+    ranges.sort_unstable();
+    let mut copies = 0;
+    let mut last: Option<(u32, i32)> = None;
+    for (seq_id, start, end) in ranges {
+        match last {
+            Some((last_id, last_end)) if last_id == seq_id && start <= last_end => {
+                last = Some((seq_id, last_end.max(end)));
+            }
+            _ => {
+                copies += 1;
+                last = Some((seq_id, end));
+            }
+        }
+    }
+    copies
+}
+
+/// Copy number of the query sequences (or PanSN samples with `by_sample`) over the target range
+/// `[start, end)`, sorted by name. Deletions in the alignments do not count as covered, and the input
+/// range is not a copy of itself.
+pub fn copy_number<'a>(impg: &'a Impg, results: &[ProjectedInterval], start: i32, end: i32, by_sample: bool) -> Vec<(&'a str, CopyNumber)> {
+    let mut groups: HashMap<&str, Vec<&ProjectedInterval>> = HashMap::new();
+    for result in results.iter().filter(|result| result.provenance.alignment_id.is_some()) {
+        let query_name = impg.seq_index.get_name(result.query_id).unwrap();
+        let key = if by_sample { pansn_sample(query_name) } else { query_name };
+        groups.entry(key).or_default().push(result);
+    }
+    let length = (end - start).max(1) as f64;
+    let mut copy_numbers: Vec<(&str, CopyNumber)> = groups.into_iter().map(|(name, results)| {
+        let copies = count_copies(results.iter().map(|r| (r.query_id, r.query_start, r.query_end)).collect());
+        let aligned: Vec<(i32, i32)> = results.iter()
+            .flat_map(|r| r.aligned_target_ranges())
+            .map(|(s, e)| (s.max(start), e.min(end)))
+            .filter(|(s, e)| s < e)
+            .collect();
+        let covered: i32 = depth_runs(std::slice::from_ref(&aligned), start, end).iter()
+            .filter(|(_, _, depth)| *depth > 0)
+            .map(|(s, e, _)| e - s)
+            .sum();
+        let aligned_bases: i64 = aligned.iter().map(|(s, e)| (e - s) as i64).sum();
+        let copy_number = CopyNumber {
+            copies,
+            covered_fraction: covered as f64 / length,
+            mean_copy_number: aligned_bases as f64 / length,
+        };
+        (name, copy_number)
+    }).collect();
+    copy_numbers.sort_by(|a, b| a.0.cmp(b.0));
+    copy_numbers
+}
+
+/// Pangenome class of a region, by the samples it is present in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PangenomeClass {
+    /// Present in all samples
+    Core,
+    /// Present in more than `cloud_fraction` of the samples, but not all
+    Shell,
+    /// Present in more than one sample, but at most `cloud_fraction` of them
+    Cloud,
+    /// Present in a single sample
+    Private,
+}
+
+impl PangenomeClass {
+    pub fn classify(present: usize, total: usize, cloud_fraction: f64) -> Self {
+        if present >= total {
+            PangenomeClass::Core
+        } else if present <= 1 {
+            PangenomeClass::Private
+        } else if present as f64 <= cloud_fraction * total as f64 {
+            PangenomeClass::Cloud
+        } else {
+            PangenomeClass::Shell
+        }
+    }
+}
+
+impl fmt::Display for PangenomeClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PangenomeClass::Core => write!(f, "core"),
+            PangenomeClass::Shell => write!(f, "shell"),
+            PangenomeClass::Cloud => write!(f, "cloud"),
+            PangenomeClass::Private => write!(f, "private"),
+        }
+    }
+}
+
+/// Classify the target range `[start, end)` into runs of constant sample presence, against all the
+/// PanSN samples of the index. Returns `(start, end, class, missing samples)`.
+pub fn classify_target<'a>(impg: &'a Impg, results: &[ProjectedInterval], start: i32, end: i32, cloud_fraction: f64) -> Vec<(i32, i32, PangenomeClass, Vec<&'a str>)> {
+    let samples = impg.seq_index.samples();
+    let by_sample = aligned_ranges_by(impg, results, true);
+    let groups: Vec<Vec<(i32, i32)>> = samples.iter()
+        .map(|sample| by_sample.get(sample).cloned().unwrap_or_default())
+        .collect();
+    presence_runs(&groups, start, end).into_iter().map(|(run_start, run_end, present)| {
+        let class = PangenomeClass::classify(present.len(), samples.len(), cloud_fraction);
+        let missing = samples.iter().enumerate()
+            .filter(|(i, _)| !present.contains(i))
+            .map(|(_, &sample)| sample)
+            .collect();
+        (run_start, run_end, class, missing)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_impg;
+
+    #[test]
+    fn test_depth_runs() {
+        let groups = vec![
+            vec![(0, 10), (5, 15)],
+            vec![(10, 20)],
+            vec![(12, 15), (15, 18)],
+        ];
+        assert_eq!(
+            depth_runs(&groups, 0, 25),
+            vec![(0, 10, 1), (10, 12, 2), (12, 15, 3), (15, 18, 2), (18, 20, 1), (20, 25, 0)]
+        );
+    }
+
+    #[test]
+    fn test_depth_runs_clipped() {
+        assert_eq!(depth_runs(&[vec![(0, 10)], vec![(8, 30)]], 5, 20), vec![(5, 8, 1), (8, 10, 2), (10, 20, 1)]);
+    }
+
+    #[test]
+    fn test_presence_runs() {
+        let groups = vec![vec![(0, 10)], vec![(5, 10)], vec![(0, 5)]];
+        assert_eq!(presence_runs(&groups, 0, 12), vec![(0, 5, vec![0, 2]), (5, 10, vec![0, 1]), (10, 12, vec![])]);
+    }
+
+    #[test]
+    fn test_count_copies() {
+        assert_eq!(count_copies(vec![(1, 0, 10), (1, 5, 15), (1, 16, 20), (2, 0, 10)]), 3);
+        // Adjacent pieces of a split projection are one copy
+        assert_eq!(count_copies(vec![(1, 0, 10), (1, 5, 15), (1, 15, 20), (2, 0, 10)]), 2);
+        assert_eq!(count_copies(vec![(1, 100, 200), (1, 0, 300)]), 1);
+        assert_eq!(count_copies(Vec::new()), 0);
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(PangenomeClass::classify(10, 10, 0.15), PangenomeClass::Core);
+        assert_eq!(PangenomeClass::classify(5, 10, 0.15), PangenomeClass::Shell);
+        assert_eq!(PangenomeClass::classify(2, 20, 0.15), PangenomeClass::Cloud);
+        assert_eq!(PangenomeClass::classify(1, 10, 0.15), PangenomeClass::Private);
+    }
+
+    #[test]
+    fn test_target_depth_and_copy_number() {
+        // A has a 10-base deletion at 40-50 of T, B covers T fully
+        let paf_data = "A\t90\t0\t90\t+\tT\t100\t0\t100\t90\t100\t60\tcg:Z:40=10D50=\n\
+                        B\t100\t0\t100\t+\tT\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n";
+        let impg = test_impg("depth", paf_data);
+        let target_id = impg.seq_index.get_id("T").unwrap();
+        // Splitting at the deletion gives two abutting pieces of A
+        let results = impg.project(target_id, 0, 100, Some(5)).unwrap();
+        assert_eq!(results.iter().filter(|r| r.query_id == impg.seq_index.get_id("A").unwrap()).count(), 2);
+
+        // The input range itself does not count
+        assert_eq!(target_depth(&impg, &results, 0, 100, false), vec![(0, 40, 2), (40, 50, 1), (50, 100, 2)]);
+
+        let copy_numbers = copy_number(&impg, &results, 0, 100, false);
+        let names: Vec<&str> = copy_numbers.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["A", "B"]);
+        assert_eq!(copy_numbers[0].1, CopyNumber { copies: 1, covered_fraction: 0.9, mean_copy_number: 0.9 });
+        assert_eq!(copy_numbers[1].1, CopyNumber { copies: 1, covered_fraction: 1.0, mean_copy_number: 1.0 });
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use noodles::core::{Position, Region};
+use noodles::fasta;
+use crate::paf::Strand;
+
+type IndexedFastaReader = fasta::IndexedReader<fasta::io::BufReader<File>>;
+
+/// Random access to the sequences of several faidx-indexed (optionally bgzipped) FASTA files
+pub struct FastaIndex {
+    readers: Vec<Mutex<IndexedFastaReader>>,
+    name_to_reader: HashMap<String, usize>,
+}
+
+impl FastaIndex {
+    /// Open the FASTA files, which must have a `.fai` index (and a `.gzi` index if bgzipped)
+    pub fn from_paths(fasta_files: &[String]) -> io::Result<Self> {
+        let mut readers = Vec::with_capacity(fasta_files.len());
+        let mut name_to_reader = HashMap::new();
+        for (i, fasta_file) in fasta_files.iter().enumerate() {
+            let reader = fasta::indexed_reader::Builder::default()
+                .build_from_path(fasta_file)
+                .map_err(|e| io::Error::new(e.kind(), format!("Could not open {} and its index: {}", fasta_file, e)))?;
+            for record in reader.index() {
+                name_to_reader.insert(String::from_utf8_lossy(record.name()).into_owned(), i);
+            }
+            readers.push(Mutex::new(reader));
+        }
+        Ok(Self { readers, name_to_reader })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.name_to_reader.contains_key(name)
+    }
+
+    /// Fetch the 0-based half-open range `[start, end)` of a sequence
+    pub fn fetch(&self, name: &str, start: i32, end: i32) -> io::Result<Vec<u8>> {
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let reader = self.name_to_reader.get(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Sequence {} not found in the FASTA files", name)))?;
+        let invalid_range = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid range {}:{}-{}", name, start, end));
+        let region_start = Position::try_from(start as usize + 1).map_err(|_| invalid_range())?;
+        let region_end = Position::try_from(end as usize).map_err(|_| invalid_range())?;
+        let region = Region::new(name, region_start..=region_end);
+
+        let record = self.readers[*reader].lock().unwrap().query(&region)?;
+        Ok(record.sequence().as_ref().to_vec())
+    }
+
+    /// Fetch a range, reverse complemented if it is on the reverse strand
+    pub fn fetch_oriented(&self, name: &str, start: i32, end: i32, strand: Strand) -> io::Result<Vec<u8>> {
+        let sequence = self.fetch(name, start, end)?;
+        Ok(match strand {
+            Strand::Forward => sequence,
+            Strand::Reverse => reverse_complement(&sequence),
+        })
+    }
+}
+
+/// Reverse complement a nucleotide sequence, keeping IUPAC ambiguity codes and case
+pub fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence.iter().rev().map(|&base| match base {
+        b'A' => b'T', b'C' => b'G', b'G' => b'C', b'T' => b'A',
+        b'a' => b't', b'c' => b'g', b'g' => b'c', b't' => b'a',
+        b'R' => b'Y', b'Y' => b'R', b'K' => b'M', b'M' => b'K',
+        b'r' => b'y', b'y' => b'r', b'k' => b'm', b'm' => b'k',
+        b'B' => b'V', b'V' => b'B', b'D' => b'H', b'H' => b'D',
+        b'b' => b'v', b'v' => b'b', b'd' => b'h', b'h' => b'd',
+        other => other,
+    }).collect()
+}
+
+/// Write a FASTA record, wrapping the sequence every `line_width` bases
+pub fn write_fasta_record<W: Write>(writer: &mut W, name: &str, sequence: &[u8], line_width: usize) -> io::Result<()> {
+    writeln!(writer, ">{}", name)?;
+    for line in sequence.chunks(line_width.max(1)) {
+        writer.write_all(line)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reverse_complement() {
+        assert_eq!(reverse_complement(b"ACGTNacgtR"), b"YacgtNACGT".to_vec());
+        assert_eq!(reverse_complement(b"aacg"), b"cgtt".to_vec());
+    }
+
+    #[test]
+    fn test_write_fasta_record() {
+        let mut out = Vec::new();
+        write_fasta_record(&mut out, "A:0-5(+)", b"ACGTA", 2).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), ">A:0-5(+)\nAC\nGT\nA\n");
+    }
+}
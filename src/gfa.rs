@@ -0,0 +1,270 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+use crate::faidx::FastaIndex;
+use crate::impg::{Impg, ProjectedInterval};
+
+/// A sequence graph: node sequences, and paths as lists of (0-based) node indexes, all in forward orientation
+#[derive(Debug, Default, PartialEq)]
+pub struct Graph {
+    pub nodes: Vec<Vec<u8>>,
+    pub paths: Vec<Vec<usize>>,
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Induce a graph seqwish-style from the alignments of the results against the target: bases matching
+/// the same target base are merged into the same graph position, as are equal bases mismatching it (a
+/// shared alternate allele). Inserted bases are never merged. Runs of positions always
+/// traversed together are compacted into nodes. `sequences[i]` is the sequence of `results[i]` in the
+/// orientation of the target and `target_seq` the target sequence starting at `target_offset`.
+/// There is one path per result.
+pub fn induce_graph(results: &[ProjectedInterval], sequences: &[Vec<u8>], target_seq: &[u8], target_offset: i32) -> Graph {
+    // One element per target base, then one per base of every sequence
+    let mut offsets = Vec::with_capacity(sequences.len());
+    let mut total = target_seq.len();
+    for sequence in sequences {
+        offsets.push(total);
+        total += sequence.len();
+    }
+    let mut parent: Vec<usize> = (0..total).collect();
+    let base_of = |element: usize| -> u8 {
+        if element < target_seq.len() {
+            return target_seq[element].to_ascii_uppercase();
+        }
+        let row = offsets.partition_point(|&offset| offset <= element) - 1;
+        sequences[row][element - offsets[row]].to_ascii_uppercase()
+    };
+
+    // First base of each alternate allele seen at a target position
+    let mut alleles: HashMap<(usize, u8), usize> = HashMap::new();
+    for (row, (result, sequence)) in results.iter().zip(sequences).enumerate() {
+        let mut t = result.target_start - target_offset;
+        let mut q = 0;
+        for op in &result.cigar {
+            let len = op.len() as usize;
+            match op.op() {
+                '=' | 'M' => {
+                    for k in 0..len {
+                        let target_element = t as usize + k;
+                        if q + k < sequence.len() && target_element < target_seq.len() {
+                            let element = offsets[row] + q + k;
+                            if base_of(element) == base_of(target_element) {
+                                let (a, b) = (find(&mut parent, element), find(&mut parent, target_element));
+                                parent[a.max(b)] = a.min(b);
+                            }
+                        }
+                    }
+                    t += len as i32;
+                    q += len;
+                }
+                'X' => {
+                    for k in 0..len {
+                        let target_element = t as usize + k;
+                        if q + k < sequence.len() && target_element < target_seq.len() {
+                            let element = offsets[row] + q + k;
+                            let allele = *alleles.entry((target_element, base_of(element))).or_insert(element);
+                            let (a, b) = (find(&mut parent, element), find(&mut parent, allele));
+                            parent[a.max(b)] = a.min(b);
+                        }
+                    }
+                    t += len as i32;
+                    q += len;
+                }
+                'I' => q += len,
+                'D' => t += len as i32,
+                _ => {}
+            }
+        }
+    }
+
+    // Graph positions in order of first traversal, and the walk of each path through them
+    let mut positions: HashMap<usize, usize> = HashMap::new();
+    let mut position_bases: Vec<u8> = Vec::new();
+    let walks: Vec<Vec<usize>> = sequences.iter().enumerate().map(|(row, sequence)| {
+        (0..sequence.len()).map(|k| {
+            let element = offsets[row] + k;
+            let root = find(&mut parent, element);
+            *positions.entry(root).or_insert_with(|| {
+                position_bases.push(base_of(element));
+                position_bases.len() - 1
+            })
+        }).collect()
+    }).collect();
+
+    // A position continues the node of its predecessor when it is its only successor, it has no
+    // other predecessor, and no path starts or ends between them
+    let n = position_bases.len();
+    let mut successors: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); n];
+    let mut predecessors: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); n];
+    let mut boundary = vec![false; n];
+    for walk in &walks {
+        for pair in walk.windows(2) {
+            successors[pair[0]].insert(pair[1]);
+            predecessors[pair[1]].insert(pair[0]);
+        }
+        if let (Some(&first), Some(&last)) = (walk.first(), walk.last()) {
+            boundary[first] = true;
+            successors[last].insert(usize::MAX);
+        }
+    }
+    let continues = |p: usize| -> Option<usize> {
+        if boundary[p] || predecessors[p].len() != 1 {
+            return None;
+        }
+        let previous = *predecessors[p].iter().next().unwrap();
+        (previous != p && successors[previous].len() == 1).then_some(previous)
+    };
+
+    let mut node_of = vec![usize::MAX; n];
+    let mut nodes: Vec<Vec<u8>> = Vec::new();
+    for start in 0..n {
+        if node_of[start] != usize::MAX || continues(start).is_some() {
+            continue;
+        }
+        let node = nodes.len();
+        let mut sequence = Vec::new();
+        let mut p = start;
+        loop {
+            node_of[p] = node;
+            sequence.push(position_bases[p]);
+            match successors[p].iter().next() {
+                Some(&next) if successors[p].len() == 1 && next != usize::MAX
+                    && node_of[next] == usize::MAX && continues(next) == Some(p) => p = next,
+                _ => break,
+            }
+        }
+        nodes.push(sequence);
+    }
+    // Cycles where every position continues its predecessor
+    for start in 0..n {
+        if node_of[start] == usize::MAX {
+            node_of[start] = nodes.len();
+            nodes.push(vec![position_bases[start]]);
+        }
+    }
+
+    // Paths enter nodes at their first position and leave them at their last one
+    let mut node_starts = vec![false; n];
+    for p in 0..n {
+        node_starts[p] = match continues(p) {
+            Some(previous) => node_of[previous] != node_of[p],
+            None => true,
+        };
+    }
+    let paths = walks.iter().map(|walk| {
+        walk.iter().filter(|&&p| node_starts[p]).map(|&p| node_of[p]).collect()
+    }).collect();
+
+    Graph { nodes, paths }
+}
+
+/// Write the graph in GFA v1, with 1-based node ids
+pub fn write_gfa<W: Write>(writer: &mut W, graph: &Graph, path_names: &[String]) -> io::Result<()> {
+    writeln!(writer, "H\tVN:Z:1.0")?;
+    for (i, sequence) in graph.nodes.iter().enumerate() {
+        writeln!(writer, "S\t{}\t{}", i + 1, String::from_utf8_lossy(sequence))?;
+    }
+    let mut links = BTreeSet::new();
+    for path in &graph.paths {
+        for pair in path.windows(2) {
+            links.insert((pair[0], pair[1]));
+        }
+    }
+    for (from, to) in links {
+        writeln!(writer, "L\t{}\t+\t{}\t+\t0M", from + 1, to + 1)?;
+    }
+    for (name, path) in path_names.iter().zip(&graph.paths) {
+        let steps: Vec<String> = path.iter().map(|node| format!("{}+", node + 1)).collect();
+        writeln!(writer, "P\t{}\t{}\t*", name, steps.join(","))?;
+    }
+    Ok(())
+}
+
+/// Induce the graph of the results with their sequences from the FASTA files, and write it as GFA
+/// with paths named `seq_name:start-end`. Results without query bases are left out.
+pub fn write_region_gfa<W: Write>(writer: &mut W, impg: &Impg, target_id: u32, results: &[ProjectedInterval], fasta_index: &FastaIndex) -> io::Result<()> {
+    let results: Vec<ProjectedInterval> = results.iter()
+        .filter(|result| result.query_start < result.query_end)
+        .cloned()
+        .collect();
+    let (target_start, target_end) = match (results.iter().map(|r| r.target_start).min(), results.iter().map(|r| r.target_end).max()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Ok(()),
+    };
+    let target_seq = fasta_index.fetch(impg.seq_index.get_name(target_id).unwrap(), target_start, target_end)?;
+    let mut sequences = Vec::with_capacity(results.len());
+    let mut path_names = Vec::with_capacity(results.len());
+    for result in &results {
+        let query_name = impg.seq_index.get_name(result.query_id).unwrap();
+        sequences.push(fasta_index.fetch_oriented(query_name, result.query_start, result.query_end, result.strand)?);
+        path_names.push(format!("{}:{}-{}", query_name, result.query_start, result.query_end));
+    }
+    let graph = induce_graph(&results, &sequences, &target_seq, target_start);
+    write_gfa(writer, &graph, &path_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impg::CigarOp;
+
+    fn result(target_start: i32, target_end: i32, cigar: &[(i32, char)]) -> ProjectedInterval {
+        ProjectedInterval {
+            target_start,
+            target_end,
+            cigar: cigar.iter().map(|&(len, op)| CigarOp::new(len, op)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn spell(graph: &Graph, path: usize) -> String {
+        graph.paths[path].iter().map(|&node| String::from_utf8(graph.nodes[node].clone()).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_induce_graph_snv_and_insertion() {
+        let results = vec![
+            result(0, 8, &[(8, '=')]),
+            result(0, 8, &[(3, '='), (1, 'X'), (2, '='), (2, 'I'), (2, '=')]),
+        ];
+        let sequences = vec![b"ACGTACGT".to_vec(), b"ACGAACttGT".to_vec()];
+        let graph = induce_graph(&results, &sequences, b"ACGTACGT", 0);
+        assert_eq!(spell(&graph, 0), "ACGTACGT");
+        assert_eq!(spell(&graph, 1), "ACGAACTTGT");
+        // ACG, T, AC, GT, A, TT
+        assert_eq!(graph.nodes.len(), 6);
+        assert_eq!(graph.paths[0], vec![0, 1, 2, 3]);
+        assert_eq!(graph.paths[1], vec![0, 4, 2, 5, 3]);
+    }
+
+    #[test]
+    fn test_induce_graph_identical() {
+        let results = vec![result(10, 14, &[(4, '=')]), result(10, 14, &[(4, '=')])];
+        let sequences = vec![b"ACGT".to_vec(), b"ACGT".to_vec()];
+        let graph = induce_graph(&results, &sequences, b"ACGT", 10);
+        assert_eq!(graph, Graph { nodes: vec![b"ACGT".to_vec()], paths: vec![vec![0], vec![0]] });
+    }
+
+    #[test]
+    fn test_induce_graph_shared_allele() {
+        // The last two results share the alternate allele A, the first one has C
+        let results = vec![
+            result(0, 8, &[(3, '='), (1, 'X'), (4, '=')]),
+            result(0, 8, &[(3, '='), (1, 'X'), (4, '=')]),
+            result(0, 8, &[(3, '='), (1, 'X'), (4, '=')]),
+        ];
+        let sequences = vec![b"ACGCACGT".to_vec(), b"ACGAACGT".to_vec(), b"ACGaACGT".to_vec()];
+        let graph = induce_graph(&results, &sequences, b"ACGTACGT", 0);
+        // ACG, C, ACGT, A
+        assert_eq!(graph.nodes.len(), 4);
+        assert_eq!(graph.paths[0], vec![0, 1, 2]);
+        assert_eq!(graph.paths[1], vec![0, 3, 2]);
+        assert_eq!(graph.paths[2], graph.paths[1]);
+    }
+}
@@ -1,44 +1,129 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
 use coitrees::{BasicCOITree, Interval, IntervalTree};
 use crate::paf::{PafRecord, ParseErr, Strand};
 use crate::seqidx::SequenceIndex;
 use serde::{Serialize, Deserialize};
-use std::io::{Read, SeekFrom, Seek};
+use std::io::{self, Read, SeekFrom, Seek};
 use std::fs::File;
+use std::fmt;
+use std::sync::OnceLock;
 use rayon::prelude::*;
 use noodles::bgzf;
 use regex::Regex;
 
+/// Errors raised while building, loading or querying an index
+#[derive(Debug)]
+pub enum ImpgError {
+    Io(io::Error),
+    Parse(ParseErr),
+    /// The `.gzi` index of a bgzipped PAF file could not be read
+    GziIndex(String, io::Error),
+    UnknownSequenceName(String),
+    UnknownSequenceId(u32),
+    /// The range `start-end` is not within the sequence (id, length)
+    InvalidRange { seq_id: u32, start: i32, end: i32, length: usize },
+    InvalidCigar(String),
+}
+
+impl fmt::Display for ImpgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImpgError::Io(e) => write!(f, "I/O error: {}", e),
+            ImpgError::Parse(e) => write!(f, "Failed to parse PAF records: {:?}", e),
+            ImpgError::GziIndex(path, e) => write!(f, "Could not open {}: {}", path, e),
+            ImpgError::UnknownSequenceName(name) => write!(f, "Sequence {} not found in index", name),
+            ImpgError::UnknownSequenceId(id) => write!(f, "Sequence id {} not found in index", id),
+            ImpgError::InvalidRange { seq_id, start, end, length } => write!(f, "Range {}-{} is not within sequence {} of length {}", start, end, seq_id, length),
+            ImpgError::InvalidCigar(cigar) => write!(f, "Invalid CIGAR string: {}", cigar),
+        }
+    }
+}
+
+impl std::error::Error for ImpgError {}
+
+impl From<io::Error> for ImpgError {
+    fn from(e: io::Error) -> Self {
+        ImpgError::Io(e)
+    }
+}
+
+impl From<ParseErr> for ImpgError {
+    fn from(e: ParseErr) -> Self {
+        ImpgError::Parse(e)
+    }
+}
+
+impl From<ImpgError> for io::Error {
+    fn from(e: ImpgError) -> Self {
+        match e {
+            ImpgError::Io(e) => e,
+            ImpgError::UnknownSequenceName(_) | ImpgError::UnknownSequenceId(_) | ImpgError::InvalidRange { .. } => io::Error::new(io::ErrorKind::InvalidInput, e.to_string()),
+            _ => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
+
 /// Parse a CIGAR string into a vector of CigarOp
 // Note that the query_delta is negative for reverse strand alignments
 #[derive(Clone, Debug)]
 #[derive(PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct CigarOp {
-    pub val: u32,
+    val: u32,
 }
 
 impl CigarOp {
+    /// Largest operation length that fits below the op bits
+    pub const MAX_LEN: i32 = (1 << 29) - 1;
+
+    /// Create an operation from trusted values, see `try_new` for untrusted ones.
+    ///
+    /// # Panics
+    ///
+    /// Panics on an unsupported op or a length that does not fit in 29 bits.
     pub fn new(len: i32, op: char) -> Self {
+        Self::try_new(len, op).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create an operation, rejecting unsupported ops and lengths that do not fit in 29 bits
+    pub fn try_new(len: i32, op: char) -> Result<Self, ImpgError> {
         let val = match op {
             '=' => 0,
             'X' => 1,
             'I' => 2,
             'D' => 3,
             'M' => 4,
-            _ => panic!("Invalid CIGAR operation: {}", op),
+            _ => return Err(ImpgError::InvalidCigar(format!("{}{}", len, op))),
         };
-        Self { val: (val << 29) | (len as u32) }
+        if !(0..=Self::MAX_LEN).contains(&len) {
+            return Err(ImpgError::InvalidCigar(format!("{}{}", len, op)));
+        }
+        Ok(Self { val: (val << 29) | (len as u32) })
+    }
+
+    /// Create an operation from its packed value (see `val`), rejecting unsupported ops
+    pub fn from_val(val: u32) -> Result<Self, ImpgError> {
+        if val >> 29 > 4 {
+            return Err(ImpgError::InvalidCigar(format!("packed operation {:#x}", val)));
+        }
+        Ok(Self { val })
+    }
+
+    /// Packed value of the operation: the op in the 3 most significant bits, the length in the others.
+    /// The field itself is private so that invalid operations cannot be built.
+    pub fn val(&self) -> u32 {
+        self.val
     }
 
     pub fn op(&self) -> char {
-        // two most significant bits in the val tell us the op
+        // three most significant bits in the val tell us the op, checked by try_new
         match self.val >> 29 {
             0 => '=',
             1 => 'X',
             2 => 'I',
             3 => 'D',
             4 => 'M',
-            _ => panic!("Invalid CIGAR operation: {}", self.val >> 29),
+            op => unreachable!("Invalid CIGAR operation: {}", op),
         }
     }
 
@@ -54,7 +139,7 @@ impl CigarOp {
         match self.op() {
             '=' | 'X' | 'D' | 'M' => self.len(),
             'I' => 0,
-            _ => panic!("Invalid CIGAR operation: {}", self.op()),
+            _ => unreachable!(),
         }
     }
 
@@ -62,7 +147,7 @@ impl CigarOp {
         match self.op() {
             '=' | 'X' | 'I' | 'M' => if strand == Strand::Forward { self.len() } else { -self.len() },
             'D' => 0,
-            _ => panic!("Invalid CIGAR operation: {}", self.op()),
+            _ => unreachable!(),
         }
     }
 }
@@ -71,6 +156,7 @@ impl CigarOp {
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct QueryMetadata {
     query_id: u32,
+    alignment_id: usize,
     target_start: i32,
     target_end: i32,
     query_start: i32,
@@ -81,30 +167,41 @@ pub struct QueryMetadata {
 }
 
 impl QueryMetadata {
-    fn get_cigar_ops(&self, paf_file: &String, paf_gzi_index: Option<&bgzf::gzi::Index>) -> Vec<CigarOp> {
+    fn get_cigar_ops(&self, paf_file: &str, paf_gzi_index: Option<&bgzf::gzi::Index>) -> Result<Vec<CigarOp>, ImpgError> {
         // Allocate space for cigar
         let mut cigar_buffer = vec![0; self.cigar_bytes];
 
         // Get reader and seek start of cigar str
         if [".gz", ".bgz"].iter().any(|e| paf_file.ends_with(e)) {
-            let mut reader = bgzf::Reader::new(File::open(paf_file).unwrap());
-            reader.seek_by_uncompressed_position(&paf_gzi_index.unwrap(), self.cigar_offset).unwrap();
-            reader.read_exact(&mut cigar_buffer).unwrap();
+            let paf_gzi_index = paf_gzi_index.ok_or_else(|| ImpgError::GziIndex(
+                format!("{}.gzi", paf_file), io::Error::new(io::ErrorKind::NotFound, "index not loaded")
+            ))?;
+            let mut reader = bgzf::Reader::new(File::open(paf_file)?);
+            reader.seek_by_uncompressed_position(paf_gzi_index, self.cigar_offset)?;
+            reader.read_exact(&mut cigar_buffer)?;
         } else {
-            let mut reader = File::open(paf_file).unwrap();
-            reader.seek(SeekFrom::Start(self.cigar_offset)).unwrap();
-            reader.read_exact(&mut cigar_buffer).unwrap();
+            let mut reader = File::open(paf_file)?;
+            reader.seek(SeekFrom::Start(self.cigar_offset))?;
+            reader.read_exact(&mut cigar_buffer)?;
         };
 
-        let cigar_str: &str = std::str::from_utf8(&cigar_buffer).unwrap();
-        parse_cigar_to_delta(cigar_str).ok().unwrap_or_else(Vec::new)
+        let cigar_str = std::str::from_utf8(&cigar_buffer).map_err(|_| ImpgError::InvalidCigar(String::from_utf8_lossy(&cigar_buffer).into_owned()))?;
+        parse_cigar_to_delta(cigar_str)
     }
 }
 
+/// Projected query interval, CIGAR and target interval, as returned by `Impg::query`. The query strand is
+/// encoded by the order of its coordinates and the target interval metadata is `0`; prefer `ProjectedInterval`.
 pub type AdjustedInterval = (Interval<u32>, Vec<CigarOp>, Interval<u32>);
 type TreeMap = HashMap<u32, BasicCOITree<QueryMetadata, u32>>;
+/// Alignments by (query id, target id), over query coordinates
+type QueryTreeMap = HashMap<(u32, u32), BasicCOITree<QueryMetadata, u32>>;
 pub type SerializableImpg = (HashMap<u32, Vec<SerializableInterval>>, SequenceIndex);
 
+/// Written before the serialized index; bump the version whenever `SerializableImpg` changes layout
+pub const INDEX_MAGIC: [u8; 4] = *b"IMPG";
+pub const INDEX_VERSION: u32 = 1;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SerializableInterval {
     first: i32,
@@ -118,31 +215,29 @@ pub struct Impg {
     pub seq_index: SequenceIndex,
     pub paf_file: String,
     pub paf_gzi_index: Option<bgzf::gzi::Index>,
+    /// Built on the first back-projection
+    query_trees: OnceLock<QueryTreeMap>,
 }
 
 impl Impg {
-    pub fn from_paf_records(records: &[PafRecord], paf_file: &str) -> Result<Self, ParseErr> {
-
-        let paf_gzi_index: Option<bgzf::gzi::Index> = if [".gz", ".bgz"].iter().any(|e| paf_file.ends_with(e)) {
-            let paf_gzi_file = paf_file.to_owned() + ".gzi";
-            Some(bgzf::gzi::read(paf_gzi_file.clone()).expect(format!("Could not open {}", paf_gzi_file).as_str()))
-        } else {
-            None
-        };
+    pub fn from_paf_records(records: &[PafRecord], paf_file: &str) -> Result<Self, ImpgError> {
+        let paf_gzi_index = read_gzi_index(paf_file)?;
 
         let mut seq_index = SequenceIndex::new();
         for record in records {
-            seq_index.get_or_insert_id(&record.query_name, Some(record.target_length));
+            seq_index.get_or_insert_id(&record.query_name, Some(record.query_length));
             seq_index.get_or_insert_id(&record.target_name, Some(record.target_length));
         }
         
-        let intervals: HashMap<u32, Vec<Interval<QueryMetadata>>> = records.par_iter()
-            .filter_map(|record| {
-                let query_id = seq_index.get_id(&record.query_name).expect("Query name not found in index");
-                let target_id = seq_index.get_id(&record.target_name).expect("Target name not found in index");
+        let intervals: Vec<(u32, Interval<QueryMetadata>)> = records.par_iter()
+            .enumerate()
+            .map(|(alignment_id, record)| {
+                let query_id = seq_index.get_id(&record.query_name).ok_or_else(|| ImpgError::UnknownSequenceName(record.query_name.clone()))?;
+                let target_id = seq_index.get_id(&record.target_name).ok_or_else(|| ImpgError::UnknownSequenceName(record.target_name.clone()))?;
 
                 let query_metadata = QueryMetadata {
                     query_id,
+                    alignment_id,
                     target_start: record.target_start as i32,
                     target_end: record.target_end as i32,
                     query_start: record.query_start as i32,
@@ -152,28 +247,23 @@ impl Impg {
                     cigar_bytes: record.cigar_bytes
                 };
 
-                Some((target_id, Interval {
+                Ok((target_id, Interval {
                     first: record.target_start as i32,
                     last: record.target_end as i32,
                     metadata: query_metadata,
                 }))
-            })  // Use fold and reduce to achieve grouping
-            .fold(HashMap::new, |mut acc: HashMap<u32, Vec<Interval<QueryMetadata>>>, (target_id, interval)| {
-                acc.entry(target_id).or_default().push(interval);
-                acc
             })
-            .reduce(HashMap::new, |mut acc, part| {
-                for (key, value) in part {
-                    acc.entry(key).or_default().extend(value);
-                }
-                acc
-            });
+            .collect::<Result<_, ImpgError>>()?;
+        let mut intervals_by_target: HashMap<u32, Vec<Interval<QueryMetadata>>> = HashMap::new();
+        for (target_id, interval) in intervals {
+            intervals_by_target.entry(target_id).or_default().push(interval);
+        }
 
-        let trees: TreeMap = intervals.into_iter().map(|(target_id, interval_nodes)| {
+        let trees: TreeMap = intervals_by_target.into_iter().map(|(target_id, interval_nodes)| {
             (target_id, BasicCOITree::new(interval_nodes.as_slice()))
         }).collect();
 
-        Ok(Self { trees, seq_index, paf_file: paf_file.to_string(), paf_gzi_index })
+        Ok(Self { trees, seq_index, paf_file: paf_file.to_string(), paf_gzi_index, query_trees: OnceLock::new() })
     }
 
     pub fn to_serializable(&self) -> SerializableImpg {
@@ -188,14 +278,9 @@ impl Impg {
         (serializable_trees, self.seq_index.clone())
     }
 
-    pub fn from_paf_and_serializable(paf_file: &str, serializable: SerializableImpg) -> Self {
+    pub fn from_paf_and_serializable(paf_file: &str, serializable: SerializableImpg) -> Result<Self, ImpgError> {
         let (serializable_trees, seq_index) = serializable;
-        let paf_gzi_index: Option<bgzf::gzi::Index> = if [".gz", ".bgz"].iter().any(|e| paf_file.ends_with(e)) {
-            let paf_gzi_file = paf_file.to_owned() + ".gzi";
-            Some(bgzf::gzi::read(paf_gzi_file.clone()).expect(format!("Could not open {}", paf_gzi_file).as_str()))
-        } else {
-            None
-        };
+        let paf_gzi_index = read_gzi_index(paf_file)?;
         let trees = serializable_trees.into_iter().map(|(target_id, intervals)| {
             let tree = BasicCOITree::new(intervals.iter().map(|interval| Interval {
                 first: interval.first,
@@ -204,84 +289,44 @@ impl Impg {
             }).collect::<Vec<_>>().as_slice());
             (target_id, tree)
         }).collect();
-        Self { trees, seq_index, paf_file: paf_file.to_string(), paf_gzi_index }
+        Ok(Self { trees, seq_index, paf_file: paf_file.to_string(), paf_gzi_index, query_trees: OnceLock::new() })
     }
 
-    pub fn query(&self, target_id: u32, range_start: i32, range_end: i32) -> Vec<AdjustedInterval> {
-        let mut results = Vec::new();
-        // add the input range to the results
-        results.push((
-            Interval {
-                first: range_start,
-                last: range_end,
-                metadata: target_id,
-            },
-            vec![CigarOp::new(range_end - range_start, '=')],
-            Interval {
-                first: range_start,
-                last: range_end,
-                metadata: 0
-            }
-        ));
+    /// Check that `[range_start, range_end)` is a valid range of the sequence `seq_id`
+    pub fn check_range(&self, seq_id: u32, range_start: i32, range_end: i32) -> Result<(), ImpgError> {
+        let length = self.seq_index.get_len_from_id(seq_id).ok_or(ImpgError::UnknownSequenceId(seq_id))?;
+        if range_start < 0 || range_start > range_end || range_end as i64 > length as i64 {
+            return Err(ImpgError::InvalidRange { seq_id, start: range_start, end: range_end, length });
+        }
+        Ok(())
+    }
+
+    /// The alignments overlapping the target range, each restricted to it: the 0-based index of the
+    /// PAF record and the projected range with its alignment against the target range (several ranges
+    /// per alignment when split at gaps longer than `split_gap`)
+    fn hops(&self, target_id: u32, range_start: i32, range_end: i32, split_gap: Option<u32>) -> Result<Vec<(usize, AdjustedInterval)>, ImpgError> {
+        let mut hops = Vec::new();
+        let mut error = None;
         if let Some(tree) = self.trees.get(&target_id) {
             tree.query(range_start, range_end, |interval| {
+                if error.is_some() {
+                    return;
+                }
                 let metadata = &interval.metadata;
-                let (adjusted_query_start, adjusted_query_end, adjusted_cigar, adjusted_target_start, adjusted_target_end) = 
-                project_target_range_through_alignment(
+                let cigar_ops = match metadata.get_cigar_ops(&self.paf_file, self.paf_gzi_index.as_ref()) {
+                    Ok(cigar_ops) => cigar_ops,
+                    Err(e) => {
+                        error = Some(e);
+                        return;
+                    }
+                };
+                let pieces = project_target_range_through_alignment_split(
                     (range_start, range_end),
                     (metadata.target_start, metadata.target_end, metadata.query_start, metadata.query_end, metadata.strand),
-                    &metadata.get_cigar_ops(&self.paf_file, self.paf_gzi_index.as_ref())
+                    &cigar_ops,
+                    split_gap
                 );
-
-                let adjusted_interval = (
-                    Interval {
-                        first: adjusted_query_start,
-                        last: adjusted_query_end,
-                        metadata: metadata.query_id
-                    },
-                    adjusted_cigar,
-                    Interval {
-                        first: adjusted_target_start,
-                        last: adjusted_target_end,
-                        metadata: 0
-                    }
-                );
-                results.push(adjusted_interval);
-            });
-        }
-        results
-    }
-
-    pub fn query_transitive(&self, target_id: u32, range_start: i32, range_end: i32) -> Vec<AdjustedInterval> {
-        let mut results = Vec::new();
-        // add the input range to the results
-        results.push((
-            Interval {
-                first: range_start,
-                last: range_end,
-                metadata: target_id,
-            },
-            vec![CigarOp::new(range_end - range_start, '=')],
-            Interval {
-                first: range_start,
-                last: range_end,
-                metadata: 0
-            }
-        ));
-        let mut stack = vec![(target_id, range_start, range_end)];
-        let mut visited = HashSet::new();
-
-        while let Some((current_target, current_start, current_end)) = stack.pop() {
-            if let Some(tree) = self.trees.get(&current_target) {
-                tree.query(current_start, current_end, |interval| {
-                    let metadata = &interval.metadata;
-                    let (adjusted_query_start, adjusted_query_end, adjusted_cigar, adjusted_target_start, adjusted_target_end) = 
-                    project_target_range_through_alignment(
-                        (current_start, current_end),
-                        (metadata.target_start, metadata.target_end, metadata.query_start, metadata.query_end, metadata.strand),
-                        &metadata.get_cigar_ops(&self.paf_file, self.paf_gzi_index.as_ref())
-                    );
-
+                for (adjusted_query_start, adjusted_query_end, adjusted_cigar, adjusted_target_start, adjusted_target_end) in pieces {
                     let adjusted_interval = (
                         Interval {
                             first: adjusted_query_start,
@@ -292,35 +337,699 @@ impl Impg {
                         Interval {
                             first: adjusted_target_start,
                             last: adjusted_target_end,
-                            metadata: 0
+                            metadata: target_id
                         }
                     );
-                    results.push(adjusted_interval);
+                    hops.push((metadata.alignment_id, adjusted_interval));
+                }
+            });
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(hops),
+        }
+    }
 
-                    if metadata.query_id != current_target {
-                        let todo_range = (metadata.query_id, adjusted_query_start, adjusted_query_end);
-                        if !visited.insert(todo_range) {
-                            stack.push(todo_range);
-                        }
+    /// Project the target range onto all the sequences aligned to it. The first result is the input range itself.
+    /// With `split_gap`, projections are split at the insertions and deletions longer than it instead of spanning them.
+    pub fn project(&self, target_id: u32, range_start: i32, range_end: i32, split_gap: Option<u32>) -> Result<Vec<ProjectedInterval>, ImpgError> {
+        self.check_range(target_id, range_start, range_end)?;
+        let mut results = Vec::new();
+        // add the input range to the results
+        results.push(ProjectedInterval::input(target_id, range_start, range_end));
+        for (alignment_id, adjusted_interval) in self.hops(target_id, range_start, range_end, split_gap)? {
+            let provenance = Provenance {
+                alignment_id: Some(alignment_id),
+                parent: Some(0),
+                depth: 1,
+                hop_target_id: target_id,
+                hop_target_start: adjusted_interval.2.first,
+                hop_target_end: adjusted_interval.2.last,
+                hop_cigar: adjusted_interval.1.clone(),
+            };
+            results.push(ProjectedInterval::from_adjusted(adjusted_interval, provenance));
+        }
+        Ok(results)
+    }
+
+    /// Project the target range transitively: every projected range is in turn projected onto the sequences
+    /// aligned to it, until closure. Results are aligned against the original target range, composing
+    /// the alignments through all the hops. The first result is the input range itself. See `project` for `split_gap`.
+    pub fn project_transitive(&self, target_id: u32, range_start: i32, range_end: i32, split_gap: Option<u32>) -> Result<Vec<ProjectedInterval>, ImpgError> {
+        self.check_range(target_id, range_start, range_end)?;
+        let mut results = Vec::new();
+        // add the input range to the results
+        results.push(ProjectedInterval::input(target_id, range_start, range_end));
+
+        // Each range still to be projected carries its alignment against the original target range,
+        // its index in the results and its hop depth
+        let input = results[0].to_adjusted_with_target_id();
+        let mut stack = vec![(input, 0, 0)];
+        let mut visited: HashMap<u32, Vec<(i32, i32)>> = HashMap::new();
+        visited.entry(target_id).or_default().push((range_start, range_end));
+
+        while let Some((current, current_index, current_depth)) = stack.pop() {
+            let current_target = current.0.metadata;
+            let (current_start, current_end) = (current.0.first.min(current.0.last), current.0.first.max(current.0.last));
+
+            for (alignment_id, hop) in self.hops(current_target, current_start, current_end, split_gap)? {
+                let composed = compose_alignments(&current, &hop);
+                let (query, hop_cigar, hop_target) = hop;
+
+                if query.metadata != current_target {
+                    let todo_range = (query.first.min(query.last), query.first.max(query.last));
+                    let ranges = visited.entry(query.metadata).or_default();
+                    if !ranges.iter().any(|&(start, end)| start <= todo_range.0 && todo_range.1 <= end) {
+                        ranges.push(todo_range);
+                        stack.push((composed.clone(), results.len(), current_depth + 1));
                     }
+                }
+                let provenance = Provenance {
+                    alignment_id: Some(alignment_id),
+                    parent: Some(current_index),
+                    depth: current_depth + 1,
+                    hop_target_id: current_target,
+                    hop_target_start: hop_target.first,
+                    hop_target_end: hop_target.last,
+                    hop_cigar,
+                };
+                results.push(ProjectedInterval::from_adjusted(composed, provenance));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Project the target range onto the destination sequences only, through the best paths of
+    /// alignments from the target range (as ranked by `cost`), for sequences without a direct alignment.
+    /// Every range reached is projected once, through its best path, and ranges of the destination
+    /// sequences are not projected further; overlapping projections on the destination sequences are
+    /// reported once. Results are aligned against the target range, and their provenance describes
+    /// the last hop of their path (`parent` is `None`, as the other ranges of the path are not returned).
+    pub fn project_path(&self, target_id: u32, range_start: i32, range_end: i32, destination_ids: &[u32], cost: PathCost) -> Result<Vec<ProjectedInterval>, ImpgError> {
+        self.check_range(target_id, range_start, range_end)?;
+        let input = ProjectedInterval::input(target_id, range_start, range_end);
+        if destination_ids.contains(&target_id) {
+            return Ok(vec![input]);
+        }
+
+        // Ranges reached so far, with their alignment against the target range, their provenance and
+        // the divergence (-ln identity) accumulated along their path; the queue orders them by cost
+        let mut reached = vec![(input.to_adjusted_with_target_id(), input.provenance, 0.0)];
+        let mut queue = BinaryHeap::from([Reverse(PathKey(0.0, 0.0, 0))]);
+        let mut settled: HashMap<u32, Vec<(i32, i32)>> = HashMap::new();
+        let mut results: Vec<ProjectedInterval> = Vec::new();
+
+        while let Some(Reverse(PathKey(_, _, index))) = queue.pop() {
+            let (current, provenance, divergence) = reached[index].clone();
+            let current_target = current.0.metadata;
+            let (current_start, current_end) = (current.0.first.min(current.0.last), current.0.first.max(current.0.last));
+            let ranges = settled.entry(current_target).or_default();
+            if ranges.iter().any(|&(start, end)| start <= current_start && current_end <= end) {
+                continue;
+            }
+            ranges.push((current_start, current_end));
+
+            if destination_ids.contains(&current_target) {
+                let overlaps = results.iter().any(|r| {
+                    r.query_id == current_target && r.query_start < current_end && current_start < r.query_end
                 });
+                if !overlaps {
+                    results.push(ProjectedInterval::from_adjusted(current, Provenance { parent: None, ..provenance }));
+                }
+                continue;
+            }
+
+            for (alignment_id, hop) in self.hops(current_target, current_start, current_end, None)? {
+                let (query, hop_cigar, hop_target) = &hop;
+                if query.metadata == current_target {
+                    continue;
+                }
+                let composed = compose_alignments(&current, &hop);
+                let depth = provenance.depth + 1;
+                let divergence = divergence - AlignmentStats::from_cigar(hop_cigar).identity().ln();
+                let key = match cost {
+                    PathCost::Hops => PathKey(depth as f64, divergence, reached.len()),
+                    PathCost::Identity => PathKey(divergence, depth as f64, reached.len()),
+                };
+                let hop_provenance = Provenance {
+                    alignment_id: Some(alignment_id),
+                    parent: Some(index),
+                    depth,
+                    hop_target_id: current_target,
+                    hop_target_start: hop_target.first,
+                    hop_target_end: hop_target.last,
+                    hop_cigar: hop_cigar.clone(),
+                };
+                reached.push((composed, hop_provenance, divergence));
+                queue.push(Reverse(key));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Project a range of a query sequence back onto a target sequence, through the inverse of the
+    /// alignments of the query sequence against it. Returns the target ranges it projects to.
+    pub fn project_back(&self, query_id: u32, range_start: i32, range_end: i32, target_id: u32) -> Result<Vec<(i32, i32)>, ImpgError> {
+        self.check_range(query_id, range_start, range_end)?;
+        let tree = match self.query_trees().get(&(query_id, target_id)) {
+            Some(tree) => tree,
+            None => return Ok(Vec::new()),
+        };
+        let mut alignments = Vec::new();
+        tree.query(range_start, range_end, |interval| {
+            // Interval ends are inclusive in the tree
+            if interval.first < range_end && range_start < interval.last {
+                alignments.push(interval.metadata.clone());
+            }
+        });
+        let mut ranges = Vec::new();
+        for metadata in alignments {
+            let cigar_ops = metadata.get_cigar_ops(&self.paf_file, self.paf_gzi_index.as_ref())?;
+            let (first, last) = match metadata.strand {
+                Strand::Forward => (metadata.query_start, metadata.query_end),
+                Strand::Reverse => (metadata.query_end, metadata.query_start),
+            };
+            let alignment = (
+                Interval { first, last, metadata: query_id },
+                cigar_ops,
+                Interval { first: metadata.target_start, last: metadata.target_end, metadata: target_id },
+            );
+            let (_, inverted_cigar, _) = invert_alignment(&alignment);
+            let (target_first, target_last, _, _, _) = project_target_range_through_alignment(
+                (range_start, range_end),
+                (metadata.query_start, metadata.query_end, metadata.target_start, metadata.target_end, metadata.strand),
+                &inverted_cigar
+            );
+            ranges.push((target_first.min(target_last), target_first.max(target_last)));
+        }
+        Ok(ranges)
+    }
+
+    fn query_trees(&self) -> &QueryTreeMap {
+        self.query_trees.get_or_init(|| {
+            let mut intervals: HashMap<(u32, u32), Vec<Interval<QueryMetadata>>> = HashMap::new();
+            for (&target_id, tree) in &self.trees {
+                for interval in tree.iter() {
+                    let metadata = interval.metadata.clone();
+                    intervals.entry((metadata.query_id, target_id)).or_default().push(Interval {
+                        first: metadata.query_start,
+                        last: metadata.query_end,
+                        metadata,
+                    });
+                }
+            }
+            intervals.into_iter().map(|(key, intervals)| (key, BasicCOITree::new(intervals.as_slice()))).collect()
+        })
+    }
+
+    /// Back-projection concordance of a result of the query of `target_id:range_start-range_end`: the
+    /// fraction of the target bases its range projects back to that lie within the queried range. Results
+    /// projecting back to several copies (e.g. collapsed duplications) score lower. `None` if the sequence
+    /// of the result has no alignment against the target sequence (e.g. results of further hops).
+    pub fn reciprocal_concordance(&self, result: &ProjectedInterval, target_id: u32, range_start: i32, range_end: i32) -> Result<Option<f64>, ImpgError> {
+        if result.provenance.alignment_id.is_none() {
+            return Ok(Some(1.0));
+        }
+        let ranges = self.project_back(result.query_id, result.query_start, result.query_end, target_id)?;
+        let total: i64 = ranges.iter().map(|(start, end)| (end - start) as i64).sum();
+        if total == 0 {
+            return Ok(None);
+        }
+        let concordant: i64 = ranges.iter()
+            .map(|&(start, end)| (end.min(range_end) - start.max(range_start)).max(0) as i64)
+            .sum();
+        Ok(Some(concordant as f64 / total as f64))
+    }
+
+    /// Tuple-based version of `project`, kept for compatibility.
+    pub fn query(&self, target_id: u32, range_start: i32, range_end: i32) -> Result<Vec<AdjustedInterval>, ImpgError> {
+        Ok(self.project(target_id, range_start, range_end, None)?.iter().map(ProjectedInterval::to_adjusted).collect())
+    }
+
+    /// Tuple-based version of `project_transitive`, kept for compatibility.
+    pub fn query_transitive(&self, target_id: u32, range_start: i32, range_end: i32) -> Result<Vec<AdjustedInterval>, ImpgError> {
+        Ok(self.project_transitive(target_id, range_start, range_end, None)?.iter().map(ProjectedInterval::to_adjusted).collect())
+    }
+}
+
+/// A range of a query sequence projected onto a target range, with the alignment between them
+#[derive(Clone, Debug, Default)]
+pub struct ProjectedInterval {
+    pub query_id: u32,
+    /// Start of the query range (always <= `query_end`)
+    pub query_start: i32,
+    pub query_end: i32,
+    pub target_id: u32,
+    pub target_start: i32,
+    pub target_end: i32,
+    /// Strand of the query range relative to the target
+    pub strand: Strand,
+    /// Alignment of the query range against the target range, in target order
+    pub cigar: Vec<CigarOp>,
+    pub provenance: Provenance,
+}
+
+impl ProjectedInterval {
+    fn input(target_id: u32, range_start: i32, range_end: i32) -> Self {
+        let cigar = vec![CigarOp::new(range_end - range_start, '=')];
+        Self {
+            query_id: target_id,
+            query_start: range_start,
+            query_end: range_end,
+            target_id,
+            target_start: range_start,
+            target_end: range_end,
+            strand: Strand::Forward,
+            cigar: cigar.clone(),
+            provenance: Provenance {
+                hop_target_id: target_id,
+                hop_target_start: range_start,
+                hop_target_end: range_end,
+                hop_cigar: cigar,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Build from a tuple whose target interval metadata holds the target id
+    fn from_adjusted(adjusted: AdjustedInterval, provenance: Provenance) -> Self {
+        let (query, cigar, target) = adjusted;
+        let (query_start, query_end, strand) = if query.first <= query.last {
+            (query.first, query.last, Strand::Forward)
+        } else {
+            (query.last, query.first, Strand::Reverse)
+        };
+        Self {
+            query_id: query.metadata,
+            query_start,
+            query_end,
+            target_id: target.metadata,
+            target_start: target.first,
+            target_end: target.last,
+            strand,
+            cigar,
+            provenance,
+        }
+    }
+
+    /// Convert to the `AdjustedInterval` tuple, where the strand is encoded by the order of the query
+    /// coordinates and the metadata of the target interval is `0`.
+    pub fn to_adjusted(&self) -> AdjustedInterval {
+        let mut adjusted = self.to_adjusted_with_target_id();
+        adjusted.2.metadata = 0;
+        adjusted
+    }
+
+    fn to_adjusted_with_target_id(&self) -> AdjustedInterval {
+        let (first, last) = match self.strand {
+            Strand::Forward => (self.query_start, self.query_end),
+            Strand::Reverse => (self.query_end, self.query_start),
+        };
+        (
+            Interval {
+                first,
+                last,
+                metadata: self.query_id
+            },
+            self.cigar.clone(),
+            Interval {
+                first: self.target_start,
+                last: self.target_end,
+                metadata: self.target_id
+            }
+        )
+    }
+
+    pub fn stats(&self) -> AlignmentStats {
+        AlignmentStats::from_cigar(&self.cigar)
+    }
+
+    /// Restrict to the target range `[start, end)`, clipping the query range and the CIGAR accordingly
+    pub fn clip_to_target(&self, start: i32, end: i32) -> Self {
+        let (first, last, cigar, target_start, target_end) = project_target_range_through_alignment(
+            (start, end),
+            (self.target_start, self.target_end, self.query_start, self.query_end, self.strand),
+            &self.cigar,
+        );
+        Self {
+            query_start: first.min(last),
+            query_end: first.max(last),
+            target_start,
+            target_end,
+            cigar,
+            ..self.clone()
+        }
+    }
+
+    /// Target ranges where the query has aligned (matching or mismatching) bases, i.e. without deletions
+    pub fn aligned_target_ranges(&self) -> Vec<(i32, i32)> {
+        let mut ranges: Vec<(i32, i32)> = Vec::new();
+        let mut t = self.target_start;
+        for op in &self.cigar {
+            if matches!(op.op(), '=' | 'X' | 'M') {
+                match ranges.last_mut() {
+                    Some(last) if last.1 == t => last.1 = t + op.len(),
+                    _ => ranges.push((t, t + op.len())),
+                }
             }
+            t += op.target_delta();
         }
+        ranges
+    }
+}
 
-        results
+/// How `Impg::project_path` ranks the paths to the destination sequences
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathCost {
+    /// Fewest alignments, then highest identity
+    Hops,
+    /// Highest identity (the product of the identities of the alignments), then fewest alignments
+    Identity,
+}
+
+/// Priority of a range in the path search: primary and secondary costs, then index of the range
+#[derive(PartialEq)]
+struct PathKey(f64, f64, usize);
+
+impl Eq for PathKey {}
+
+impl Ord for PathKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+            .then(self.1.total_cmp(&other.1))
+            .then(self.2.cmp(&other.2))
     }
 }
 
+impl PartialOrd for PathKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Where a projected interval comes from
+#[derive(Clone, Debug, Default)]
+pub struct Provenance {
+    /// 0-based index of the PAF record the interval was projected through, `None` for the input range
+    pub alignment_id: Option<usize>,
+    /// Index in the results of the interval it was projected from, `None` for the input range
+    pub parent: Option<usize>,
+    /// Number of alignments traversed from the input range
+    pub depth: usize,
+    /// Range of the parent sequence the interval was projected from in the last hop
+    pub hop_target_id: u32,
+    pub hop_target_start: i32,
+    pub hop_target_end: i32,
+    /// Alignment of the last hop against that range
+    pub hop_cigar: Vec<CigarOp>,
+}
+
+/// Base counts of an alignment
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AlignmentStats {
+    /// Matching bases (`=`, or `M` where matches and mismatches are not distinguished)
+    pub matches: i32,
+    pub mismatches: i32,
+    /// Bases inserted in the query
+    pub insertions: i32,
+    /// Bases deleted from the query
+    pub deletions: i32,
+    /// Insertion and deletion operations
+    pub gap_opens: i32,
+}
+
+impl AlignmentStats {
+    pub fn from_cigar(cigar: &[CigarOp]) -> Self {
+        let mut stats = Self::default();
+        for op in cigar {
+            match op.op() {
+                '=' | 'M' => stats.matches += op.len(),
+                'X' => stats.mismatches += op.len(),
+                'I' => {
+                    stats.insertions += op.len();
+                    stats.gap_opens += 1;
+                }
+                'D' => {
+                    stats.deletions += op.len();
+                    stats.gap_opens += 1;
+                }
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Alignment block length, i.e. the number of alignment columns
+    pub fn block_len(&self) -> i32 {
+        self.matches + self.mismatches + self.insertions + self.deletions
+    }
+
+    /// Fraction of matching alignment columns (BLAST identity)
+    pub fn identity(&self) -> f64 {
+        if self.block_len() == 0 {
+            0.0
+        } else {
+            self.matches as f64 / self.block_len() as f64
+        }
+    }
+
+    /// Identity counting each gap as a single difference, whatever its length
+    pub fn gap_compressed_identity(&self) -> f64 {
+        let columns = self.matches + self.mismatches + self.gap_opens;
+        if columns == 0 {
+            0.0
+        } else {
+            self.matches as f64 / columns as f64
+        }
+    }
+
+    /// Number of mismatching, inserted and deleted bases (`NM`)
+    pub fn edit_distance(&self) -> i32 {
+        self.mismatches + self.insertions + self.deletions
+    }
+}
+
+/// Coverage of a target range by a result, with the stats of its alignment (`--alignment-stats`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TargetStats {
+    /// Aligned target bases of the result within the target range
+    pub covered: i32,
+    /// Fraction of the target range they make up
+    pub covered_fraction: f64,
+    pub alignment: AlignmentStats,
+}
+
+impl TargetStats {
+    pub fn new(result: &ProjectedInterval, start: i32, end: i32) -> Self {
+        let covered: i32 = result.aligned_target_ranges().iter()
+            .map(|&(s, e)| (e.min(end) - s.max(start)).max(0))
+            .sum();
+        Self {
+            covered,
+            covered_fraction: covered as f64 / (end - start).max(1) as f64,
+            alignment: result.stats(),
+        }
+    }
+
+    /// Extra BED/BEDPE columns: covered bases and fraction, matches, mismatches, inserted and deleted
+    /// bases, gap-compressed and BLAST identity
+    pub fn columns(&self) -> String {
+        let stats = &self.alignment;
+        format!("\t{}\t{:.6}\t{}\t{}\t{}\t{}\t{:.6}\t{:.6}",
+                self.covered, self.covered_fraction, stats.matches, stats.mismatches, stats.insertions, stats.deletions,
+                stats.gap_compressed_identity(), stats.identity())
+    }
+
+    /// Extra PAF tags: `cv:i:`, `cf:f:`, `NM:i:`, `gi:f:` and `id:f:`
+    pub fn tags(&self) -> String {
+        let stats = &self.alignment;
+        format!("\tcv:i:{}\tcf:f:{:.6}\tNM:i:{}\tgi:f:{:.6}\tid:f:{:.6}",
+                self.covered, self.covered_fraction, stats.edit_distance(), stats.gap_compressed_identity(), stats.identity())
+    }
+}
+
+/// Swap the query and the target of an alignment: insertions become deletions and vice versa, and
+/// the CIGAR is reversed on the reverse strand to follow the new target.
+pub fn invert_alignment(alignment: &AdjustedInterval) -> AdjustedInterval {
+    let (query, cigar, target) = alignment;
+    let reverse = query.first > query.last;
+    let swap = |op: &CigarOp| match op.op() {
+        'I' => CigarOp::new(op.len(), 'D'),
+        'D' => CigarOp::new(op.len(), 'I'),
+        _ => op.clone(),
+    };
+    let cigar: Vec<CigarOp> = if reverse {
+        cigar.iter().rev().map(swap).collect()
+    } else {
+        cigar.iter().map(swap).collect()
+    };
+    let (first, last) = if reverse { (target.last, target.first) } else { (target.first, target.last) };
+    (
+        Interval { first, last, metadata: target.metadata },
+        cigar,
+        Interval { first: query.first.min(query.last), last: query.first.max(query.last), metadata: query.metadata },
+    )
+}
+
+/// Compose the alignment `first` of a middle sequence M (as query) against a target T with the alignment
+/// `second` of a query Q against M (as target), giving the alignment of Q against T. The M range of `second`
+/// must lie within the M range of `first`. Matches are kept only where both alignments match, and the
+/// metadata of the resulting target interval is the one of `first`.
+pub fn compose_alignments(first: &AdjustedInterval, second: &AdjustedInterval) -> AdjustedInterval {
+    let (first_query, first_cigar, first_target) = first;
+    let (second_query, second_cigar, second_target) = second;
+
+    // Walk `first` along increasing M positions
+    let first_reverse = first_query.first > first_query.last;
+    let first_ops: Vec<CigarOp> = if first_reverse {
+        first_cigar.iter().rev().cloned().collect()
+    } else {
+        first_cigar.clone()
+    };
+
+    // Clip `first` to the M range of `second`, keeping track of the T bases skipped before it
+    let (m_start, m_end) = (second_target.first, second_target.last);
+    let mut m_pos = first_query.first.min(first_query.last);
+    let mut t_skipped = 0;
+    let mut clipped: Vec<(char, i32)> = Vec::new();
+    for op in &first_ops {
+        let len = op.len();
+        match op.op() {
+            'D' => {
+                if m_pos <= m_start {
+                    t_skipped += len;
+                } else if m_pos < m_end {
+                    clipped.push(('D', len));
+                }
+            },
+            op_char => {
+                let before = (m_start - m_pos).clamp(0, len);
+                let inside = (m_end.min(m_pos + len) - m_start.max(m_pos)).max(0);
+                if op_char != 'I' {
+                    t_skipped += before;
+                }
+                if inside > 0 {
+                    clipped.push((op_char, inside));
+                }
+                m_pos += len;
+            },
+        }
+        if m_pos >= m_end && m_pos > m_start {
+            break;
+        }
+    }
+    let t_len: i32 = clipped.iter().filter(|(op, _)| *op != 'I').map(|(_, len)| len).sum();
+
+    // Merge both alignments along M
+    let mut composed: Vec<CigarOp> = Vec::new();
+    let mut push = |op: char, len: i32| {
+        if len == 0 {
+            return;
+        }
+        match composed.last_mut() {
+            Some(last) if last.op() == op => *last = CigarOp::new(last.len() + len, op),
+            _ => composed.push(CigarOp::new(len, op)),
+        }
+    };
+    let mut first_iter = clipped.into_iter();
+    let mut second_iter = second_cigar.iter().map(|op| (op.op(), op.len()));
+    let mut first_op = first_iter.next();
+    let mut second_op = second_iter.next();
+    loop {
+        match (first_op, second_op) {
+            (Some(('D', len)), _) => {
+                push('D', len);
+                first_op = first_iter.next();
+            },
+            (_, Some(('I', len))) => {
+                push('I', len);
+                second_op = second_iter.next();
+            },
+            (Some((a, a_len)), Some((b, b_len))) => {
+                let len = a_len.min(b_len);
+                match (a, b) {
+                    ('I', 'D') => {},
+                    ('I', _) => push('I', len),
+                    (_, 'D') => push('D', len),
+                    ('=', '=') => push('=', len),
+                    ('M', _) | (_, 'M') => push('M', len),
+                    _ => push('X', len),
+                }
+                first_op = if a_len > len { Some((a, a_len - len)) } else { first_iter.next() };
+                second_op = if b_len > len { Some((b, b_len - len)) } else { second_iter.next() };
+            },
+            (Some((a, len)), None) => {
+                if a != 'I' {
+                    push('D', len);
+                }
+                first_op = first_iter.next();
+            },
+            (None, Some((b, len))) => {
+                if b != 'D' {
+                    push('I', len);
+                }
+                second_op = second_iter.next();
+            },
+            (None, None) => break,
+        }
+    }
+
+    let (target_start, target_end) = if first_reverse {
+        composed.reverse();
+        (first_target.last - t_skipped - t_len, first_target.last - t_skipped)
+    } else {
+        (first_target.first + t_skipped, first_target.first + t_skipped + t_len)
+    };
+    let (query_min, query_max) = (second_query.first.min(second_query.last), second_query.first.max(second_query.last));
+    let second_reverse = second_query.first > second_query.last;
+    let (query_first, query_last) = if first_reverse != second_reverse {
+        (query_max, query_min)
+    } else {
+        (query_min, query_max)
+    };
+
+    (
+        Interval {
+            first: query_first,
+            last: query_last,
+            metadata: second_query.metadata
+        },
+        composed,
+        Interval {
+            first: target_start,
+            last: target_end,
+            metadata: first_target.metadata
+        }
+    )
+}
+
 fn project_target_range_through_alignment(
     target_range: (i32, i32),
     record: (i32, i32, i32, i32, Strand),
     cigar_ops: &[CigarOp]
 ) -> (i32, i32, Vec<CigarOp>, i32, i32) {
+    project_target_range_through_alignment_split(target_range, record, cigar_ops, None).pop().unwrap()
+}
+
+/// Project the target range through the alignment, splitting the projection at insertions and
+/// deletions longer than `max_gap`: the gaps are left out and each piece gets its own sub-CIGAR.
+/// Pieces without any alignment operation are dropped. Without `max_gap`, there is a single projection.
+fn project_target_range_through_alignment_split(
+    target_range: (i32, i32),
+    record: (i32, i32, i32, i32, Strand),
+    cigar_ops: &[CigarOp],
+    max_gap: Option<u32>
+) -> Vec<(i32, i32, Vec<CigarOp>, i32, i32)> {
     let (target_start, target_end, query_start, query_end, strand) = record;
+    let is_split_gap = |len: i32| max_gap.is_some_and(|max_gap| len as u32 > max_gap);
 
     let mut target_pos = target_start;
     let mut query_pos = if strand == Strand::Forward { query_start } else { query_end };
 
+    let mut pieces = Vec::new();
+    let mut split = false;
     let mut projected_start: Option<i32> = None;
     let mut projected_end: Option<i32> = None;
     let mut projected_cigar = Vec::new();
@@ -333,15 +1042,21 @@ fn project_target_range_through_alignment(
         if target_pos > target_range.1 {
             break;
         }
+        // Whether the operation is a gap to split the projection at
+        let mut gap = false;
         match (cigar_op.target_delta(), cigar_op.query_delta(strand)) {
             (0, query_delta) => { // Insertion in query (deletions in target)
                 if target_pos >= target_range.0 && target_pos <= target_range.1 {
-                    projected_start.get_or_insert(query_pos);
-                    projected_end = Some(query_pos + query_delta);
-                    projected_cigar.push(CigarOp::new(query_delta.abs(), 'I'));
-
-                    new_target_start.get_or_insert(target_pos);
-                    new_target_end = Some(target_pos);
+                    if is_split_gap(query_delta.abs()) {
+                        gap = true;
+                    } else {
+                        projected_start.get_or_insert(query_pos);
+                        projected_end = Some(query_pos + query_delta);
+                        projected_cigar.push(CigarOp::new(query_delta.abs(), 'I'));
+
+                        new_target_start.get_or_insert(target_pos);
+                        new_target_end = Some(target_pos);
+                    }
                 }
                 query_pos += query_delta;
             },
@@ -349,7 +1064,9 @@ fn project_target_range_through_alignment(
                 let overlap_start = target_pos.max(target_range.0);
                 let overlap_end = (target_pos + target_delta).min(target_range.1);
 
-                if overlap_start < overlap_end { // There's an overlap
+                if overlap_start < overlap_end && is_split_gap(target_delta) {
+                    gap = true;
+                } else if overlap_start < overlap_end { // There's an overlap
                     projected_start.get_or_insert(query_pos);
                     projected_end = Some(query_pos); // Deletion does not advance query position
 
@@ -383,18 +1100,33 @@ fn project_target_range_through_alignment(
                 query_pos += query_delta;
             },
         }
+        if gap {
+            split = true;
+            if !projected_cigar.is_empty() {
+                pieces.push((
+                    projected_start.take().unwrap(),
+                    projected_end.take().unwrap().min(query_end),
+                    std::mem::take(&mut projected_cigar),
+                    new_target_start.take().unwrap(),
+                    new_target_end.take().unwrap().min(target_end),
+                ));
+            }
+        }
     }
-    
-    (
-        projected_start.unwrap_or(query_start),
-        (projected_end.unwrap_or(query_pos)).min(query_end),
-        projected_cigar,
-        new_target_start.unwrap_or(target_start),
-        (new_target_end.unwrap_or(target_pos)).min(target_end),
-    )
+
+    if !split || !projected_cigar.is_empty() {
+        pieces.push((
+            projected_start.unwrap_or(query_start),
+            (projected_end.unwrap_or(query_pos)).min(query_end),
+            projected_cigar,
+            new_target_start.unwrap_or(target_start),
+            (new_target_end.unwrap_or(target_pos)).min(target_end),
+        ));
+    }
+    pieces
 }
 
-fn parse_cigar_to_delta(cigar: &str) -> Result<Vec<CigarOp>, ParseErr> {
+fn parse_cigar_to_delta(cigar: &str) -> Result<Vec<CigarOp>, ImpgError> {
     let mut ops = Vec::new();
     let mut num_buf = String::new();
 
@@ -402,17 +1134,28 @@ fn parse_cigar_to_delta(cigar: &str) -> Result<Vec<CigarOp>, ParseErr> {
         if c.is_ascii_digit() {
             num_buf.push(c);
         } else {
-            let len = num_buf.parse::<i32>().map_err(|_| ParseErr::InvalidCigarFormat)?;
+            let len = num_buf.parse::<i32>().map_err(|_| ImpgError::InvalidCigar(cigar.to_string()))?;
             num_buf.clear(); // Reset the buffer for the next operation
             // raise any error from the cigar op parsing
-            let op = CigarOp::new(len, c);
-            ops.push(op);
+            ops.push(CigarOp::try_new(len, c)?);
         }
     }
 
     Ok(ops)
 }
 
+/// Read the `.gzi` index of a bgzipped PAF file, if the PAF file is compressed
+fn read_gzi_index(paf_file: &str) -> Result<Option<bgzf::gzi::Index>, ImpgError> {
+    if [".gz", ".bgz"].iter().any(|e| paf_file.ends_with(e)) {
+        let paf_gzi_file = paf_file.to_owned() + ".gzi";
+        bgzf::gzi::read(&paf_gzi_file)
+            .map(Some)
+            .map_err(|e| ImpgError::GziIndex(paf_gzi_file, e))
+    } else {
+        Ok(None)
+    }
+}
+
 fn is_valid_cigar(cigar: &[CigarOp]) -> Result<(), String> {
     let cigar_str: String = cigar.iter().map(|op| format!("{}{}", op.len(), op.op())).collect();
 
@@ -448,17 +1191,19 @@ fn parse_cigar(cigar: &[CigarOp]) -> (i32, i32) {
     (query_length, target_length)
 }
 
-pub fn check_intervals(impg: &Impg, results: &Vec<AdjustedInterval>) -> Vec<(String, String)> {
+pub fn check_intervals(impg: &Impg, results: &[ProjectedInterval]) -> Vec<(String, String)> {
     let mut invalid = Vec::new();
 
-    for (overlap_query, cigar, overlap_target) in results {
-        let query_name = impg.seq_index.get_name(overlap_query.metadata).unwrap();
-        let query_len = impg.seq_index.get_len_from_id(overlap_query.metadata).unwrap();
-        let target_name = impg.seq_index.get_name(overlap_target.metadata).unwrap();
-        let target_len = impg.seq_index.get_len_from_id(overlap_target.metadata).unwrap();
+    for result in results {
+        let cigar = &result.cigar;
+        let query_name = impg.seq_index.get_name(result.query_id).unwrap();
+        let query_len = impg.seq_index.get_len_from_id(result.query_id).unwrap();
+        let target_name = impg.seq_index.get_name(result.target_id).unwrap();
+        let target_len = impg.seq_index.get_len_from_id(result.target_id).unwrap();
 
-        let (query_start, query_end) = (overlap_query.first, overlap_query.last);
-        let (target_start, target_end) = (overlap_target.first, overlap_target.last);
+        let (query_start, query_end) = (result.query_start, result.query_end);
+        let (target_start, target_end) = (result.target_start, result.target_end);
+        let strand = if result.strand == Strand::Forward { '+' } else { '-' };
 
         let full_cigar: String = cigar.iter().map(|op| format!("{}{}", op.len(), op.op())).collect();
         let first_chunk_cigar = if full_cigar.len() > 20 {
@@ -481,7 +1226,7 @@ pub fn check_intervals(impg: &Impg, results: &Vec<AdjustedInterval>) -> Vec<(Str
             Ok(()) => {
                 if !error_details.is_empty() {
                     let error_reason = error_details.join("; ");
-                    invalid.push((format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", query_name, query_len, query_start, query_end, if query_start <= query_end { '+' } else { '-' }, target_name, target_len, target_start, target_end, first_chunk_cigar), error_reason));
+                    invalid.push((format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", query_name, query_len, query_start, query_end, strand, target_name, target_len, target_start, target_end, first_chunk_cigar), error_reason));
                 }
             }
             Err(error_msg) => {
@@ -490,7 +1235,7 @@ pub fn check_intervals(impg: &Impg, results: &Vec<AdjustedInterval>) -> Vec<(Str
                 } else {
                     format!("{}; {}", error_msg, error_details.join("; "))
                 };
-                invalid.push((format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", query_name, query_len, query_start, query_end, if query_start <= query_end { '+' } else { '-' }, target_name, target_len, target_start, target_end, first_chunk_cigar), error_reason));
+                invalid.push((format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", query_name, query_len, query_start, query_end, strand, target_name, target_len, target_start, target_end, first_chunk_cigar), error_reason));
             }
         }
     }
@@ -503,6 +1248,7 @@ mod tests {
     use super::*;
     use std::io::BufReader;
     use crate::paf::parse_paf;
+    use crate::test_util::test_impg;
 
     #[test]
     fn test_project_target_range_through_alignment_forward() {
@@ -660,6 +1406,278 @@ mod tests {
         assert_eq!((query_start, query_end, cigar, target_start, target_end), (0, 10, vec![CigarOp::new(10, '=')], 0, 10));
     }
 
+    fn adjusted(query: (i32, i32, u32), cigar: Vec<CigarOp>, target: (i32, i32, u32)) -> AdjustedInterval {
+        (
+            Interval { first: query.0, last: query.1, metadata: query.2 },
+            cigar,
+            Interval { first: target.0, last: target.1, metadata: target.2 },
+        )
+    }
+
+    // coitrees intervals are not comparable
+    fn flatten(interval: &AdjustedInterval) -> (i32, i32, u32, Vec<CigarOp>, i32, i32, u32) {
+        let (query, cigar, target) = interval;
+        (query.first, query.last, query.metadata, cigar.clone(), target.first, target.last, target.metadata)
+    }
+
+    #[test]
+    fn test_compose_alignments_identity() {
+        let identity = adjusted((100, 200, 0), vec![CigarOp::new(100, '=')], (100, 200, 0));
+        let hop = adjusted((60, 150, 1), vec![CigarOp::new(50, '='), CigarOp::new(10, 'D'), CigarOp::new(40, '=')], (100, 200, 0));
+        assert_eq!(flatten(&compose_alignments(&identity, &hop)), flatten(&hop));
+    }
+
+    #[test]
+    fn test_compose_alignments_forward() {
+        // M[10, 40) against T[100, 130) with a mismatch, Q[0, 23) against M[15, 35)
+        let first = adjusted((10, 40, 1), vec![CigarOp::new(10, '='), CigarOp::new(1, 'X'), CigarOp::new(19, '=')], (100, 130, 0));
+        let second = adjusted((0, 23, 2), vec![CigarOp::new(3, '='), CigarOp::new(5, 'I'), CigarOp::new(10, '='), CigarOp::new(2, 'D'), CigarOp::new(5, '=')], (15, 35, 1));
+        let composed = compose_alignments(&first, &second);
+        assert_eq!(flatten(&composed), flatten(&adjusted(
+            (0, 23, 2),
+            vec![CigarOp::new(3, '='), CigarOp::new(5, 'I'), CigarOp::new(2, '='), CigarOp::new(1, 'X'), CigarOp::new(7, '='), CigarOp::new(2, 'D'), CigarOp::new(5, '=')],
+            (105, 125, 0)
+        )));
+
+        // The mismatch of the first alignment is kept
+        let second = adjusted((0, 10, 2), vec![CigarOp::new(10, '=')], (15, 25, 1));
+        let composed = compose_alignments(&first, &second);
+        assert_eq!(composed.1, vec![CigarOp::new(5, '='), CigarOp::new(1, 'X'), CigarOp::new(4, '=')]);
+        assert_eq!((composed.2.first, composed.2.last), (105, 115));
+    }
+
+    #[test]
+    fn test_compose_alignments_reverse() {
+        // M[670, 750) on the reverse strand of T[250, 330), then Q[150, 225) on the reverse strand of M[670, 750)
+        let first = adjusted((750, 670, 1), vec![CigarOp::new(80, '=')], (250, 330, 0));
+        let second = adjusted((225, 150, 2), vec![CigarOp::new(30, '='), CigarOp::new(5, 'D'), CigarOp::new(45, '=')], (670, 750, 1));
+        let composed = compose_alignments(&first, &second);
+        assert_eq!(flatten(&composed), flatten(&adjusted(
+            (150, 225, 2),
+            vec![CigarOp::new(45, '='), CigarOp::new(5, 'D'), CigarOp::new(30, '=')],
+            (250, 330, 0)
+        )));
+    }
+
+    #[test]
+    fn test_clip_to_target() {
+        let result = ProjectedInterval {
+            query_start: 100,
+            query_end: 125,
+            target_start: 10,
+            target_end: 30,
+            strand: Strand::Reverse,
+            cigar: vec![CigarOp::new(10, '='), CigarOp::new(5, 'I'), CigarOp::new(10, '=')],
+            ..Default::default()
+        };
+        let clipped = result.clip_to_target(15, 25);
+        assert_eq!((clipped.query_start, clipped.query_end, clipped.target_start, clipped.target_end), (105, 120, 15, 25));
+        assert_eq!(clipped.cigar, vec![CigarOp::new(5, '='), CigarOp::new(5, 'I'), CigarOp::new(5, '=')]);
+    }
+
+    #[test]
+    fn test_invert_alignment() {
+        let forward = adjusted((0, 23, 2), vec![CigarOp::new(3, '='), CigarOp::new(5, 'I'), CigarOp::new(15, '='), CigarOp::new(2, 'D')], (15, 35, 1));
+        assert_eq!(flatten(&invert_alignment(&forward)), flatten(&adjusted(
+            (15, 35, 1),
+            vec![CigarOp::new(3, '='), CigarOp::new(5, 'D'), CigarOp::new(15, '='), CigarOp::new(2, 'I')],
+            (0, 23, 2)
+        )));
+        let reverse = adjusted((225, 150, 2), vec![CigarOp::new(30, '='), CigarOp::new(5, 'D'), CigarOp::new(45, '=')], (670, 750, 1));
+        let inverted = invert_alignment(&reverse);
+        assert_eq!(flatten(&inverted), flatten(&adjusted(
+            (750, 670, 1),
+            vec![CigarOp::new(45, '='), CigarOp::new(5, 'I'), CigarOp::new(30, '=')],
+            (150, 225, 2)
+        )));
+        assert_eq!(flatten(&invert_alignment(&inverted)), flatten(&reverse));
+    }
+
+    #[test]
+    fn test_project_target_range_through_alignment_split() {
+        let cigar_ops = vec![
+            CigarOp::new(10, '='), // 10, 60
+            CigarOp::new(5, 'I'),  // 10, 65
+            CigarOp::new(5, 'D'),  // 15, 65
+            CigarOp::new(50, '='), // 65, 115
+            CigarOp::new(50, 'I'), // 65, 165
+            CigarOp::new(35, '='), // 100, 200
+        ];
+        let base = (0, 100, 50, 200, Strand::Forward);
+        let pieces = project_target_range_through_alignment_split((0, 100), base, &cigar_ops, Some(10));
+        assert_eq!(pieces, vec![
+            (50, 115, vec![CigarOp::new(10, '='), CigarOp::new(5, 'I'), CigarOp::new(5, 'D'), CigarOp::new(50, '=')], 0, 65),
+            (165, 200, vec![CigarOp::new(35, '=')], 65, 100),
+        ]);
+        // Small gaps are kept, and without threshold nothing is split
+        assert_eq!(project_target_range_through_alignment_split((0, 100), base, &cigar_ops, Some(50)).len(), 1);
+        assert_eq!(
+            project_target_range_through_alignment_split((0, 100), base, &cigar_ops, None),
+            vec![project_target_range_through_alignment((0, 100), base, &cigar_ops)]
+        );
+    }
+
+    #[test]
+    fn test_project_target_range_through_alignment_split_reverse() {
+        let cigar_ops = vec![CigarOp::new(20, '='), CigarOp::new(40, 'D'), CigarOp::new(40, '=')];
+        let base = (0, 100, 0, 60, Strand::Reverse);
+        let pieces = project_target_range_through_alignment_split((0, 100), base, &cigar_ops, Some(30));
+        assert_eq!(pieces, vec![
+            (60, 40, vec![CigarOp::new(20, '=')], 0, 20),
+            (40, 0, vec![CigarOp::new(40, '=')], 60, 100),
+        ]);
+        // Nothing is left of a range within the gap
+        assert_eq!(project_target_range_through_alignment_split((25, 55), base, &cigar_ops, Some(30)), vec![]);
+    }
+
+    #[test]
+    fn test_project_path() {
+        // B is reached from T through A in two hops with a mismatch, or through C and D in three exact hops
+        let paf_data = "A\t100\t0\t100\t+\tT\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n\
+                        B\t100\t0\t100\t+\tA\t100\t0\t100\t99\t100\t60\tcg:Z:50=1X49=\n\
+                        C\t100\t0\t100\t+\tT\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n\
+                        D\t100\t0\t100\t-\tC\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n\
+                        B\t100\t0\t100\t-\tD\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n";
+        let impg = test_impg("path", paf_data);
+
+        let t = impg.seq_index.get_id("T").unwrap();
+        let b = impg.seq_index.get_id("B").unwrap();
+        let fewest_hops = impg.project_path(t, 0, 100, &[b], PathCost::Hops).unwrap();
+        let best_identity = impg.project_path(t, 0, 100, &[b], PathCost::Identity).unwrap();
+        let to_itself = impg.project_path(t, 0, 100, &[t], PathCost::Hops).unwrap();
+
+        assert_eq!(fewest_hops.len(), 1);
+        assert_eq!((fewest_hops[0].query_id, fewest_hops[0].query_start, fewest_hops[0].query_end), (b, 0, 100));
+        assert_eq!(fewest_hops[0].cigar, vec![CigarOp::new(50, '='), CigarOp::new(1, 'X'), CigarOp::new(49, '=')]);
+        assert_eq!((fewest_hops[0].provenance.depth, fewest_hops[0].provenance.alignment_id), (2, Some(1)));
+
+        assert_eq!(best_identity.len(), 1);
+        assert_eq!(best_identity[0].strand, Strand::Forward);
+        assert_eq!(best_identity[0].cigar, vec![CigarOp::new(100, '=')]);
+        assert_eq!((best_identity[0].provenance.depth, best_identity[0].provenance.alignment_id), (3, Some(4)));
+
+        assert_eq!(to_itself.len(), 1);
+        assert_eq!(to_itself[0].query_id, t);
+    }
+
+    #[test]
+    fn test_reciprocal_concordance() {
+        // Both copies of T (0-100 and 500-600) are aligned to the same range of Q, and R aligns to Q
+        let paf_data = "Q\t100\t0\t100\t+\tT\t1000\t0\t100\t100\t100\t60\tcg:Z:100=\n\
+                        Q\t100\t0\t100\t-\tT\t1000\t500\t600\t100\t100\t60\tcg:Z:100=\n\
+                        P\t100\t0\t100\t-\tT\t1000\t200\t300\t100\t100\t60\tcg:Z:10=5D85=5I\n\
+                        R\t100\t0\t100\t+\tQ\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n";
+        let impg = test_impg("reciprocal", paf_data);
+
+        let t = impg.seq_index.get_id("T").unwrap();
+        let p = impg.seq_index.get_id("P").unwrap();
+        let collapsed = impg.project_transitive(t, 0, 100, None).unwrap();
+        let unique = impg.project(t, 200, 300, None).unwrap();
+        let scores: Vec<Option<f64>> = collapsed.iter()
+            .map(|result| impg.reciprocal_concordance(result, t, 0, 100).unwrap())
+            .collect();
+        let back = impg.project_back(p, 0, 10, t).unwrap();
+        let unique_score = impg.reciprocal_concordance(&unique[1], t, 200, 300).unwrap();
+
+        // The input range, Q and R (not aligned to T)
+        assert_eq!(scores, vec![Some(1.0), Some(0.5), None]);
+        // The end of P is aligned to the end of the target range on the reverse strand, after the insertion
+        assert_eq!(back, vec![(295, 300)]);
+        assert_eq!(unique_score, Some(1.0));
+    }
+
+    #[test]
+    fn test_project_transitive_provenance() {
+        // B is aligned to A, A is aligned to T
+        let paf_data = "A\t100\t0\t100\t+\tT\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n\
+                        B\t100\t0\t100\t-\tA\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n";
+        let impg = test_impg("provenance", paf_data);
+
+        let t = impg.seq_index.get_id("T").unwrap();
+        let b = impg.seq_index.get_id("B").unwrap();
+        let a = impg.seq_index.get_id("A").unwrap();
+        let results = impg.project_transitive(t, 10, 20, None).unwrap();
+        let tuples = impg.query_transitive(t, 10, 20).unwrap();
+        let out_of_range = impg.project(t, 90, 110, None);
+
+        assert!(matches!(out_of_range, Err(ImpgError::InvalidRange { start: 90, end: 110, length: 100, .. })));
+
+        assert_eq!(results.len(), 3);
+        let result = &results[2];
+        assert_eq!((result.query_id, result.query_start, result.query_end, result.strand), (b, 80, 90, Strand::Reverse));
+        assert_eq!((result.target_id, result.target_start, result.target_end), (t, 10, 20));
+        let provenance = &result.provenance;
+        assert_eq!((provenance.alignment_id, provenance.parent, provenance.depth), (Some(1), Some(1), 2));
+        assert_eq!((provenance.hop_target_id, provenance.hop_target_start, provenance.hop_target_end), (a, 10, 20));
+
+        // The tuple API encodes the strand in the order of the query coordinates
+        assert_eq!(flatten(&tuples[2]), (90, 80, b, vec![CigarOp::new(10, '=')], 10, 20, 0));
+    }
+
+    #[test]
+    fn test_sequence_lengths() {
+        // Query and target lengths come from their own PAF columns
+        let paf_data = "Q\t60\t0\t50\t+\tT\t100\t0\t50\t50\t50\t60\tcg:Z:50=\n";
+        let impg = test_impg("lengths", paf_data);
+        let q = impg.seq_index.get_id("Q").unwrap();
+        let t = impg.seq_index.get_id("T").unwrap();
+        assert_eq!(impg.seq_index.get_len_from_id(q), Some(60));
+        assert_eq!(impg.seq_index.get_len_from_id(t), Some(100));
+        assert!(impg.project(q, 0, 60, None).is_ok());
+        assert!(matches!(impg.project(q, 0, 100, None), Err(ImpgError::InvalidRange { length: 60, .. })));
+    }
+
+    #[test]
+    fn test_missing_gzi_index() {
+        let records = parse_paf(BufReader::new(&b"A\t100\t0\t100\t+\tT\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n"[..])).unwrap();
+        let result = Impg::from_paf_records(&records, "/nonexistent/impg_test.paf.gz");
+        assert!(matches!(result, Err(ImpgError::GziIndex(..))));
+    }
+
+    #[test]
+    fn test_alignment_stats() {
+        let stats = AlignmentStats::from_cigar(&[CigarOp::new(10, '='), CigarOp::new(2, 'X'), CigarOp::new(3, 'I'), CigarOp::new(5, 'D')]);
+        assert_eq!(stats, AlignmentStats { matches: 10, mismatches: 2, insertions: 3, deletions: 5, gap_opens: 2 });
+        assert_eq!(stats.block_len(), 20);
+        assert_eq!(stats.identity(), 0.5);
+        assert_eq!(stats.gap_compressed_identity(), 10.0 / 14.0);
+        assert_eq!(stats.edit_distance(), 10);
+    }
+
+    #[test]
+    fn test_target_stats() {
+        // Q 20-25 is inserted, T 50-52 mismatched and T 52-62 deleted
+        let paf_data = "Q\t100\t0\t100\t+\tT\t105\t0\t105\t93\t105\t60\tcg:Z:20=5I30=2X10D43=\n";
+        let impg = test_impg("target_stats", paf_data);
+        let target_id = impg.seq_index.get_id("T").unwrap();
+        let results = impg.project(target_id, 10, 90, None).unwrap();
+        assert_eq!(results.len(), 2);
+        let result = &results[1];
+        assert_eq!((result.query_start, result.query_end), (10, 85));
+
+        let stats = TargetStats::new(result, 10, 90);
+        assert_eq!(stats.covered, 70);
+        assert_eq!(stats.covered_fraction, 0.875);
+        assert_eq!(stats.alignment, AlignmentStats { matches: 68, mismatches: 2, insertions: 5, deletions: 10, gap_opens: 2 });
+        assert_eq!(stats.columns(), "\t70\t0.875000\t68\t2\t5\t10\t0.944444\t0.800000");
+        assert_eq!(stats.tags(), "\tcv:i:70\tcf:f:0.875000\tNM:i:17\tgi:f:0.944444\tid:f:0.800000");
+
+        // Coverage is relative to the given range
+        let stats = TargetStats::new(result, 40, 60);
+        assert_eq!((stats.covered, stats.covered_fraction), (12, 0.6));
+    }
+
+    #[test]
+    fn test_aligned_target_ranges() {
+        let result = ProjectedInterval {
+            target_start: 10,
+            target_end: 20,
+            cigar: vec![CigarOp::new(2, '='), CigarOp::new(1, 'X'), CigarOp::new(2, 'I'), CigarOp::new(2, 'D'), CigarOp::new(5, 'M')],
+            ..Default::default()
+        };
+        assert_eq!(result.aligned_target_ranges(), vec![(10, 13), (15, 20)]);
+    }
+
     #[test]
     fn test_parse_cigar_to_delta_basic() {
         let cigar = "10=5I5D";
@@ -672,11 +1690,34 @@ mod tests {
         assert_eq!(ops, cigar_ops);
     }
 
-    // #[test]
-    // fn test_parse_cigar_to_delta_invalid() {
-    //     let cigar = "10=5Q"; // Q is not a valid CIGAR operation
-    //     assert!(parse_cigar_to_delta(cigar).is_err());
-    // }
+    #[test]
+    fn test_parse_cigar_to_delta_invalid() {
+        let cigar = "10=5Q"; // Q is not a valid CIGAR operation
+        assert!(parse_cigar_to_delta(cigar).is_err());
+        // Lengths that would overwrite the op bits
+        assert!(parse_cigar_to_delta("536870912=").is_err());
+        assert!(parse_cigar_to_delta("=").is_err());
+    }
+
+    #[test]
+    fn test_cigar_op_try_new() {
+        let op = CigarOp::try_new(CigarOp::MAX_LEN, 'D').unwrap();
+        assert_eq!((op.len(), op.op()), (CigarOp::MAX_LEN, 'D'));
+        assert!(matches!(CigarOp::try_new(1 << 29, '='), Err(ImpgError::InvalidCigar(_))));
+        assert!(matches!(CigarOp::try_new(-1, '='), Err(ImpgError::InvalidCigar(_))));
+        assert!(matches!(CigarOp::try_new(10, 'S'), Err(ImpgError::InvalidCigar(_))));
+
+        // The packed value round-trips, and invalid ones are rejected
+        assert_eq!(op.val(), (3 << 29) | CigarOp::MAX_LEN as u32);
+        assert_eq!(CigarOp::from_val(op.val()).unwrap(), op);
+        assert!(matches!(CigarOp::from_val(5 << 29), Err(ImpgError::InvalidCigar(_))));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid CIGAR")]
+    fn test_cigar_op_new_invalid() {
+        CigarOp::new(10, 'S');
+    }
 
     #[test]
     fn test_parse_paf_valid() {
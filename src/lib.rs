@@ -2,3 +2,18 @@
 pub mod impg;
 pub mod seqidx;
 pub mod paf;
+pub mod liftover;
+pub mod faidx;
+pub mod maf;
+pub mod vcf;
+pub mod sv;
+pub mod depth;
+pub mod partition;
+pub mod similarity;
+pub mod tree;
+pub mod gfa;
+pub mod closure;
+pub mod orthology;
+
+#[cfg(test)]
+mod test_util;
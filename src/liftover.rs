@@ -0,0 +1,686 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use crate::faidx::reverse_complement;
+use crate::impg::{Impg, ImpgError, ProjectedInterval};
+use crate::paf::Strand;
+use crate::seqidx::pansn_haplotype;
+use std::collections::HashMap;
+
+/// Annotation formats that can be lifted over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureFormat {
+    Bed,
+    Gff3,
+    Gtf,
+    Vcf,
+}
+
+impl FeatureFormat {
+    /// Guess the format from the file extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let lower = path.to_lowercase();
+        if lower.ends_with(".bed") {
+            Some(FeatureFormat::Bed)
+        } else if lower.ends_with(".gff3") || lower.ends_with(".gff") {
+            Some(FeatureFormat::Gff3)
+        } else if lower.ends_with(".gtf") {
+            Some(FeatureFormat::Gtf)
+        } else if lower.ends_with(".vcf") {
+            Some(FeatureFormat::Vcf)
+        } else {
+            None
+        }
+    }
+}
+
+/// Why a feature could not be lifted over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmappedReason {
+    /// No alignment overlaps the feature
+    NoAlignment,
+    /// The feature falls entirely in a deletion of the query
+    Deleted,
+    /// The feature is covered by several alignments, none of which spans it entirely
+    Split,
+    /// A single alignment covers only part of the feature
+    Partial,
+    /// An indel lands on the reverse strand, where it cannot be re-anchored without sequences
+    ReverseStrandIndel,
+    /// The record could not be parsed
+    Malformed,
+}
+
+impl fmt::Display for UnmappedReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            UnmappedReason::NoAlignment => "NoAlignment",
+            UnmappedReason::Deleted => "Deleted",
+            UnmappedReason::Split => "Split",
+            UnmappedReason::Partial => "Partial",
+            UnmappedReason::ReverseStrandIndel => "ReverseStrandIndel",
+            UnmappedReason::Malformed => "Malformed",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Outcome of lifting a feature
+pub type LiftResult<T> = Result<T, UnmappedReason>;
+
+/// A feature range mapped onto a query sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiftedRange {
+    pub seq_id: u32,
+    pub start: i32,
+    pub end: i32,
+    pub strand: Strand,
+}
+
+/// Decide where the source range `[start, end)` lands given the projections returned by
+/// `Impg::project`. Every alignment spanning the whole range gives one lifted range.
+pub fn classify_projections(source: (i32, i32), projections: &[ProjectedInterval]) -> Result<Vec<LiftedRange>, UnmappedReason> {
+    if projections.is_empty() {
+        return Err(UnmappedReason::NoAlignment);
+    }
+
+    // Projections without any aligned base fall in a deletion of the query
+    let aligned: Vec<&ProjectedInterval> = projections.iter()
+        .filter(|p| p.cigar.iter().any(|op| matches!(op.op(), '=' | 'X' | 'M')))
+        .collect();
+    if aligned.is_empty() {
+        return Err(UnmappedReason::Deleted);
+    }
+
+    let lifted: Vec<LiftedRange> = aligned.iter()
+        .filter(|p| p.target_start <= source.0 && p.target_end >= source.1)
+        .map(|p| LiftedRange { seq_id: p.query_id, start: p.query_start, end: p.query_end, strand: p.strand })
+        .collect();
+
+    if !lifted.is_empty() {
+        Ok(lifted)
+    } else if aligned.len() > 1 {
+        Err(UnmappedReason::Split)
+    } else {
+        Err(UnmappedReason::Partial)
+    }
+}
+
+/// Lift the range `[start, end)` of the sequence `seq_name` onto the aligned queries.
+/// With `dest_prefix`, only queries whose name starts with it (e.g. a PanSN `sample#hap#`) are considered.
+/// The outer error is raised when the index cannot be queried, the inner one when the range cannot be lifted.
+pub fn lift_range(
+    impg: &Impg,
+    seq_name: &str,
+    start: i32,
+    end: i32,
+    transitive: bool,
+    dest_prefix: Option<&str>,
+) -> Result<LiftResult<Vec<LiftedRange>>, ImpgError> {
+    let seq_id = match impg.seq_index.get_id(seq_name) {
+        Some(id) => id,
+        None => return Ok(Err(UnmappedReason::NoAlignment)),
+    };
+    let results = match if transitive {
+        impg.project_transitive(seq_id, start, end, None)
+    } else {
+        impg.project(seq_id, start, end, None)
+    } {
+        Ok(results) => results,
+        Err(ImpgError::InvalidRange { .. }) => return Ok(Err(UnmappedReason::Malformed)),
+        Err(e) => return Err(e),
+    };
+
+    // The first result is the input range itself
+    let projections: Vec<ProjectedInterval> = results.into_iter()
+        .skip(1)
+        .filter(|p| {
+            let name = impg.seq_index.get_name(p.query_id).unwrap();
+            dest_prefix.map_or(name != seq_name, |prefix| name.starts_with(prefix))
+        })
+        .collect();
+
+    Ok(classify_projections((start, end), &projections))
+}
+
+/// Counts of lifted and unmapped features
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LiftoverStats {
+    pub lifted: usize,
+    pub unmapped: usize,
+}
+
+/// Lift every feature read from `reader`, writing lifted records to `lifted` and the
+/// others to `unmapped`, each preceded by a `#<reason>` line as UCSC liftOver does.
+pub fn liftover<R: BufRead, W: Write, U: Write>(
+    impg: &Impg,
+    reader: R,
+    format: FeatureFormat,
+    transitive: bool,
+    dest_prefix: Option<&str>,
+    lifted: &mut W,
+    unmapped: &mut U,
+) -> io::Result<LiftoverStats> {
+    let mut stats = LiftoverStats::default();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+            // Contig headers describe the old assembly
+            if !line.starts_with("##contig") && !line.starts_with("##sequence-region") {
+                writeln!(lifted, "{}", line)?;
+            }
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let records = match parse_record_range(&fields, format) {
+            // Empty features (e.g. BED insertion points) are lifted through their first base
+            Ok((start, end)) => lift_range(impg, fields[0], start, end.max(start + 1), transitive, dest_prefix)?
+                .and_then(|lifted| lift_record(impg, &fields, format, end > start, lifted)),
+            Err(reason) => Err(reason),
+        };
+        match records {
+            Ok(records) => {
+                for record in records {
+                    writeln!(lifted, "{}", record)?;
+                }
+                stats.lifted += 1;
+            }
+            Err(reason) => {
+                writeln!(unmapped, "#{}", reason)?;
+                writeln!(unmapped, "{}", line)?;
+                stats.unmapped += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Consistency problems of a gene model projected onto another genome
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelFlag {
+    /// Some features of the model could not be lifted
+    MissingFeatures,
+    /// Features land on different sequences of the destination haplotype
+    SplitSequences,
+    /// Exons or CDSs land on different strands
+    SplitStrands,
+    /// The CDS length of a transcript changes by a non-multiple of 3
+    Frameshift,
+}
+
+impl fmt::Display for ModelFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = match self {
+            ModelFlag::MissingFeatures => "missing_features",
+            ModelFlag::SplitSequences => "split_sequences",
+            ModelFlag::SplitStrands => "split_strands",
+            ModelFlag::Frameshift => "frameshift",
+        };
+        write!(f, "{}", flag)
+    }
+}
+
+struct GffFeature {
+    fields: Vec<String>,
+    id: Option<String>,
+    parents: Vec<String>,
+    start: i32,
+    end: i32,
+}
+
+impl GffFeature {
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<String> = line.split('\t').map(|s| s.to_string()).collect();
+        if fields.len() < 9 {
+            return None;
+        }
+        let start = fields[3].parse::<i32>().ok()? - 1;
+        let end = fields[4].parse::<i32>().ok()?;
+
+        let mut id = None;
+        let mut parents = Vec::new();
+        for attribute in fields[8].split(';') {
+            match attribute.trim().split_once('=') {
+                Some(("ID", value)) => id = Some(value.to_string()),
+                Some(("Parent", value)) => parents = value.split(',').map(|s| s.to_string()).collect(),
+                _ => {}
+            }
+        }
+
+        Some(Self { fields, id, parents, start, end })
+    }
+
+    fn feature_type(&self) -> &str {
+        &self.fields[2]
+    }
+}
+
+/// Lift whole GFF3 gene models, keeping the gene/mRNA/exon/CDS hierarchy together.
+/// Each model is projected separately onto every destination haplotype (PanSN `sample#haplotype`),
+/// and inconsistent projections are flagged with an `impg_flags` attribute on the top-level feature.
+/// Models whose top-level feature cannot be lifted anywhere are written to `unmapped`.
+pub fn liftover_gene_models<R: BufRead, W: Write, U: Write>(
+    impg: &Impg,
+    reader: R,
+    transitive: bool,
+    dest_prefix: Option<&str>,
+    lifted: &mut W,
+    unmapped: &mut U,
+) -> io::Result<LiftoverStats> {
+    let mut stats = LiftoverStats::default();
+
+    // Group features into models rooted at the features without a parent
+    let mut features: Vec<GffFeature> = Vec::new();
+    let mut roots: Vec<usize> = Vec::new();
+    let mut id_to_index: HashMap<String, usize> = HashMap::new();
+    let mut models: Vec<Vec<usize>> = Vec::new();
+    let mut root_to_model: HashMap<usize, usize> = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('#') {
+            if !line.starts_with("##sequence-region") {
+                writeln!(lifted, "{}", line)?;
+            }
+            continue;
+        }
+        let feature = match GffFeature::parse(&line) {
+            Some(feature) => feature,
+            None => {
+                writeln!(unmapped, "#{}", UnmappedReason::Malformed)?;
+                writeln!(unmapped, "{}", line)?;
+                stats.unmapped += 1;
+                continue;
+            }
+        };
+
+        let index = features.len();
+        let root = feature.parents.first()
+            .and_then(|parent| id_to_index.get(parent))
+            .map_or(index, |&parent_index| roots[parent_index]);
+        if let Some(id) = &feature.id {
+            id_to_index.insert(id.clone(), index);
+        }
+        roots.push(root);
+        features.push(feature);
+
+        let model = *root_to_model.entry(root).or_insert_with(|| {
+            models.push(Vec::new());
+            models.len() - 1
+        });
+        models[model].push(index);
+    }
+
+    for model in models {
+        let root = model[0];
+        let lifted_features = model.iter()
+            .map(|&i| lift_range(impg, &features[i].fields[0], features[i].start, features[i].end, transitive, dest_prefix))
+            .collect::<Result<Vec<_>, ImpgError>>()?;
+
+        let root_hits = match &lifted_features[0] {
+            Ok(hits) => hits,
+            Err(reason) => {
+                writeln!(unmapped, "#{}", reason)?;
+                for &i in &model {
+                    writeln!(unmapped, "{}", features[i].fields.join("\t"))?;
+                }
+                stats.unmapped += 1;
+                continue;
+            }
+        };
+
+        // Project the model once per destination haplotype, anchored on the first hit of the top-level feature
+        let mut anchors: Vec<&LiftedRange> = Vec::new();
+        for hit in root_hits {
+            let haplotype = pansn_haplotype(impg.seq_index.get_name(hit.seq_id).unwrap());
+            if !anchors.iter().any(|a| pansn_haplotype(impg.seq_index.get_name(a.seq_id).unwrap()) == haplotype) {
+                anchors.push(hit);
+            }
+        }
+
+        for anchor in anchors {
+            let haplotype = pansn_haplotype(impg.seq_index.get_name(anchor.seq_id).unwrap());
+            let mut flags: Vec<ModelFlag> = Vec::new();
+            let mut projected: Vec<(usize, &LiftedRange)> = Vec::new();
+            let mut chosen: HashMap<usize, &LiftedRange> = HashMap::new();
+            for (&i, result) in model.iter().zip(lifted_features.iter()) {
+                // Keep each feature within the hit of its parent (the anchor for the top-level feature),
+                // preferring hits on the same strand, so that paralogs elsewhere are not picked. Features
+                // that are not there but on another sequence of the haplotype are kept and flagged.
+                let locus = features[i].parents.first()
+                    .and_then(|parent| id_to_index.get(parent))
+                    .and_then(|parent| chosen.get(parent))
+                    .copied()
+                    .unwrap_or(anchor);
+                let hit = if i == root {
+                    Some(anchor)
+                } else {
+                    result.as_ref().ok().and_then(|hits| {
+                        let candidates: Vec<&LiftedRange> = hits.iter()
+                            .filter(|h| h.seq_id == locus.seq_id && h.start >= locus.start && h.end <= locus.end)
+                            .collect();
+                        candidates.iter().find(|h| h.strand == locus.strand).or(candidates.first()).copied()
+                            .or_else(|| hits.iter().find(|h| {
+                                h.seq_id != locus.seq_id && pansn_haplotype(impg.seq_index.get_name(h.seq_id).unwrap()) == haplotype
+                            }))
+                    })
+                };
+                match hit {
+                    Some(hit) => {
+                        chosen.insert(i, hit);
+                        projected.push((i, hit));
+                    }
+                    None => if !flags.contains(&ModelFlag::MissingFeatures) {
+                        flags.push(ModelFlag::MissingFeatures);
+                    },
+                }
+            }
+
+            if projected.iter().any(|(_, hit)| hit.seq_id != anchor.seq_id) {
+                flags.push(ModelFlag::SplitSequences);
+            }
+            let exons: Vec<&LiftedRange> = projected.iter()
+                .filter(|(i, _)| matches!(features[*i].feature_type(), "exon" | "CDS"))
+                .map(|(_, hit)| *hit)
+                .collect();
+            if exons.iter().any(|h| h.strand != exons[0].strand) {
+                flags.push(ModelFlag::SplitStrands);
+            }
+
+            // Compare the CDS length of each transcript before and after the projection, when all its CDSs were lifted
+            let mut cds_lengths: HashMap<&str, [(i32, usize); 2]> = HashMap::new();
+            for &i in &model {
+                if features[i].feature_type() == "CDS" {
+                    for parent in &features[i].parents {
+                        let original = &mut cds_lengths.entry(parent.as_str()).or_default()[0];
+                        *original = (original.0 + features[i].end - features[i].start, original.1 + 1);
+                    }
+                }
+            }
+            for (i, hit) in &projected {
+                if features[*i].feature_type() == "CDS" {
+                    for parent in &features[*i].parents {
+                        let projected = &mut cds_lengths.entry(parent.as_str()).or_default()[1];
+                        *projected = (projected.0 + hit.end - hit.start, projected.1 + 1);
+                    }
+                }
+            }
+            if cds_lengths.values().any(|[original, projected]| original.1 == projected.1 && (original.0 - projected.0) % 3 != 0) {
+                flags.push(ModelFlag::Frameshift);
+            }
+
+            for (i, hit) in projected {
+                let mut out = features[i].fields.clone();
+                out[0] = impg.seq_index.get_name(hit.seq_id).unwrap().to_string();
+                out[3] = (hit.start + 1).to_string();
+                out[4] = hit.end.to_string();
+                if hit.strand == Strand::Reverse {
+                    out[6] = match out[6].as_str() {
+                        "+" => "-".to_string(),
+                        "-" => "+".to_string(),
+                        other => other.to_string(),
+                    };
+                }
+                if i == root && !flags.is_empty() {
+                    let flags: Vec<String> = flags.iter().map(|f| f.to_string()).collect();
+                    out[8] = format!("{};impg_flags={}", out[8].trim_end_matches(';'), flags.join(","));
+                }
+                writeln!(lifted, "{}", out.join("\t"))?;
+            }
+        }
+        stats.lifted += 1;
+    }
+
+    Ok(stats)
+}
+
+/// Columns holding the start, end and strand of a record, and whether the coordinates are 1-based
+fn record_columns(format: FeatureFormat) -> (usize, usize, usize, bool) {
+    match format {
+        FeatureFormat::Bed => (1, 2, 5, false),
+        FeatureFormat::Gff3 | FeatureFormat::Gtf => (3, 4, 6, true),
+        FeatureFormat::Vcf => (1, 1, usize::MAX, true),
+    }
+}
+
+/// Get the 0-based half-open range of a record
+fn parse_record_range(fields: &[&str], format: FeatureFormat) -> Result<(i32, i32), UnmappedReason> {
+    let (start_col, end_col, _, one_based) = record_columns(format);
+    let min_fields = match format {
+        FeatureFormat::Bed => 3,
+        FeatureFormat::Gff3 | FeatureFormat::Gtf => 8,
+        FeatureFormat::Vcf => 5,
+    };
+    if fields.len() < min_fields {
+        return Err(UnmappedReason::Malformed);
+    }
+
+    let parse = |s: &str| s.parse::<i32>().map_err(|_| UnmappedReason::Malformed);
+    let start = parse(fields[start_col])? - if one_based { 1 } else { 0 };
+    let end = if format == FeatureFormat::Vcf {
+        start + fields[3].len() as i32
+    } else {
+        parse(fields[end_col])?
+    };
+    Ok((start, end))
+}
+
+/// Rewrite a record for each of its lifted ranges
+fn lift_record(
+    impg: &Impg,
+    fields: &[&str],
+    format: FeatureFormat,
+    non_empty: bool,
+    lifted: Vec<LiftedRange>,
+) -> Result<Vec<String>, UnmappedReason> {
+    let (start_col, end_col, strand_col, one_based) = record_columns(format);
+
+    let mut records = Vec::with_capacity(lifted.len());
+    for range in lifted {
+        let mut out: Vec<String> = fields.iter().map(|s| s.to_string()).collect();
+        out[0] = impg.seq_index.get_name(range.seq_id).unwrap().to_string();
+        out[start_col] = (range.start + if one_based { 1 } else { 0 }).to_string();
+        if format != FeatureFormat::Vcf {
+            // Empty features sit before their base, which is after it on the reverse strand
+            out[end_col] = match (non_empty, range.strand) {
+                (true, _) | (false, Strand::Reverse) => range.end,
+                (false, Strand::Forward) => range.start,
+            }.to_string();
+            if !non_empty {
+                out[start_col] = out[end_col].clone();
+            }
+        }
+
+        if range.strand == Strand::Reverse {
+            if let Some(strand) = out.get_mut(strand_col) {
+                *strand = match strand.as_str() {
+                    "+" => "-".to_string(),
+                    "-" => "+".to_string(),
+                    other => other.to_string(),
+                };
+            }
+            if format == FeatureFormat::Vcf {
+                let reverse_complement = |allele: &str| String::from_utf8_lossy(&reverse_complement(allele.as_bytes())).into_owned();
+                let alts: Vec<&str> = fields[4].split(',').collect();
+                if alts.iter().any(|alt| alt.len() != fields[3].len() && !alt.starts_with('<')) {
+                    continue;
+                }
+                out[3] = reverse_complement(fields[3]);
+                out[4] = alts.iter()
+                    .map(|alt| if alt.starts_with('<') || *alt == "." { alt.to_string() } else { reverse_complement(alt) })
+                    .collect::<Vec<_>>()
+                    .join(",");
+            }
+        }
+
+        records.push(out.join("\t"));
+    }
+
+    // Only reverse strand indels were lifted
+    if records.is_empty() {
+        return Err(UnmappedReason::ReverseStrandIndel);
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impg::CigarOp;
+    use crate::test_util::test_impg;
+    use std::io::BufReader;
+
+    fn projection(query: (i32, i32), cigar: Vec<CigarOp>, target: (i32, i32)) -> ProjectedInterval {
+        let (query_start, query_end, strand) = if query.0 <= query.1 {
+            (query.0, query.1, Strand::Forward)
+        } else {
+            (query.1, query.0, Strand::Reverse)
+        };
+        ProjectedInterval {
+            query_id: 1,
+            query_start,
+            query_end,
+            target_start: target.0,
+            target_end: target.1,
+            strand,
+            cigar,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_feature_format_from_path() {
+        assert_eq!(FeatureFormat::from_path("genes.GFF3"), Some(FeatureFormat::Gff3));
+        assert_eq!(FeatureFormat::from_path("calls.vcf"), Some(FeatureFormat::Vcf));
+        assert_eq!(FeatureFormat::from_path("regions.txt"), None);
+    }
+
+    #[test]
+    fn test_classify_projections() {
+        assert_eq!(classify_projections((10, 20), &[]), Err(UnmappedReason::NoAlignment));
+
+        let deleted = projection((50, 50), vec![CigarOp::new(10, 'D')], (10, 20));
+        assert_eq!(classify_projections((10, 20), &[deleted]), Err(UnmappedReason::Deleted));
+
+        let left = projection((50, 55), vec![CigarOp::new(5, '=')], (10, 15));
+        let right = projection((80, 85), vec![CigarOp::new(5, '=')], (15, 20));
+        assert_eq!(classify_projections((10, 20), &[left.clone(), right]), Err(UnmappedReason::Split));
+        assert_eq!(classify_projections((10, 20), &[left]), Err(UnmappedReason::Partial));
+
+        let reverse = projection((60, 50), vec![CigarOp::new(10, '=')], (10, 20));
+        assert_eq!(
+            classify_projections((10, 20), &[reverse]),
+            Ok(vec![LiftedRange { seq_id: 1, start: 50, end: 60, strand: Strand::Reverse }])
+        );
+    }
+
+    #[test]
+    fn test_gff_feature_parse() {
+        let feature = GffFeature::parse("T\t.\tCDS\t11\t20\t.\t+\t0\tID=cds1;Parent=tx1,tx2").unwrap();
+        assert_eq!((feature.start, feature.end), (10, 20));
+        assert_eq!(feature.id.as_deref(), Some("cds1"));
+        assert_eq!(feature.parents, vec!["tx1".to_string(), "tx2".to_string()]);
+        assert_eq!(feature.feature_type(), "CDS");
+        assert!(GffFeature::parse("T\t.\tgene\t11\t20").is_none());
+    }
+
+    #[test]
+    fn test_liftover() {
+        // T 0-100 is aligned to Q, and T 100-200 to R on the reverse strand
+        let paf_data = "Q\t100\t0\t100\t+\tT\t200\t0\t100\t100\t100\t60\tcg:Z:100=\n\
+                        R\t100\t0\t100\t-\tT\t200\t100\t200\t100\t100\t60\tcg:Z:100=\n";
+        let impg = test_impg("liftover", paf_data);
+
+        let bed = "track name=features\n\
+                   T\t10\t20\tf1\t0\t+\n\
+                   T\t150\t160\tf2\t0\t+\n\
+                   T\t90\t110\tf3\t0\t+\n\
+                   U\t0\t10\tf4\t0\t+\n\
+                   T\t20\t20\tp1\t0\t+\n\
+                   T\t150\t150\tp2\t0\t+\n";
+        let (mut lifted, mut unmapped) = (Vec::new(), Vec::new());
+        let stats = liftover(&impg, BufReader::new(bed.as_bytes()), FeatureFormat::Bed, false, None, &mut lifted, &mut unmapped).unwrap();
+        assert_eq!((stats.lifted, stats.unmapped), (4, 2));
+        assert_eq!(String::from_utf8(lifted).unwrap(), "track name=features\n\
+            Q\t10\t20\tf1\t0\t+\n\
+            R\t40\t50\tf2\t0\t-\n\
+            Q\t20\t20\tp1\t0\t+\n\
+            R\t50\t50\tp2\t0\t-\n");
+        assert_eq!(String::from_utf8(unmapped).unwrap(), "#Split\nT\t90\t110\tf3\t0\t+\n#NoAlignment\nU\t0\t10\tf4\t0\t+\n");
+
+        let gff = "##gff-version 3\nT\t.\tgene\t151\t160\t.\t+\t.\tID=g1\n";
+        let (mut lifted, mut unmapped) = (Vec::new(), Vec::new());
+        liftover(&impg, BufReader::new(gff.as_bytes()), FeatureFormat::Gff3, false, Some("R"), &mut lifted, &mut unmapped).unwrap();
+        assert_eq!(String::from_utf8(lifted).unwrap(), "##gff-version 3\nR\t.\tgene\t41\t50\t.\t-\t.\tID=g1\n");
+        assert!(unmapped.is_empty());
+    }
+
+    #[test]
+    fn test_liftover_gene_models() {
+        // The first exon of g1 also has a paralog at 500-560 of Q, and its second CDS spans a 1 bp deletion
+        let paf_data = "s1#1#Q\t1000\t500\t560\t+\tT\t300\t0\t60\t60\t60\t60\tcg:Z:60=\n\
+                        s1#1#Q\t1000\t0\t159\t+\tT\t300\t40\t200\t159\t160\t60\tcg:Z:80=1D79=\n";
+        let gff = "##gff-version 3\n\
+                   T\t.\tgene\t41\t150\t.\t+\t.\tID=g1\n\
+                   T\t.\tmRNA\t41\t150\t.\t+\t.\tID=m1;Parent=g1\n\
+                   T\t.\texon\t46\t60\t.\t+\t.\tID=e1;Parent=m1\n\
+                   T\t.\tCDS\t46\t60\t.\t+\t0\tID=c1;Parent=m1\n\
+                   T\t.\texon\t111\t150\t.\t+\t.\tID=e2;Parent=m1\n\
+                   T\t.\tCDS\t111\t150\t.\t+\t0\tID=c2;Parent=m1\n\
+                   T\t.\tgene\t151\t200\t.\t-\t.\tID=g2\n\
+                   T\t.\tncRNA\t151\t200\t.\t-\t.\tID=n1;Parent=g2\n\
+                   T\t.\texon\t151\t200\t.\t-\t.\tID=e3;Parent=n1\n";
+        let impg = test_impg("gene_models", paf_data);
+        let (mut lifted, mut unmapped) = (Vec::new(), Vec::new());
+        let stats = liftover_gene_models(&impg, BufReader::new(gff.as_bytes()), false, Some("s1#1#"), &mut lifted, &mut unmapped).unwrap();
+
+        // The transcript without CDS is not flagged
+        assert_eq!((stats.lifted, stats.unmapped), (2, 0));
+        assert!(unmapped.is_empty());
+        assert_eq!(String::from_utf8(lifted).unwrap(), "##gff-version 3\n\
+            s1#1#Q\t.\tgene\t1\t109\t.\t+\t.\tID=g1;impg_flags=frameshift\n\
+            s1#1#Q\t.\tmRNA\t1\t109\t.\t+\t.\tID=m1;Parent=g1\n\
+            s1#1#Q\t.\texon\t6\t20\t.\t+\t.\tID=e1;Parent=m1\n\
+            s1#1#Q\t.\tCDS\t6\t20\t.\t+\t0\tID=c1;Parent=m1\n\
+            s1#1#Q\t.\texon\t71\t109\t.\t+\t.\tID=e2;Parent=m1\n\
+            s1#1#Q\t.\tCDS\t71\t109\t.\t+\t0\tID=c2;Parent=m1\n\
+            s1#1#Q\t.\tgene\t110\t159\t.\t-\t.\tID=g2\n\
+            s1#1#Q\t.\tncRNA\t110\t159\t.\t-\t.\tID=n1;Parent=g2\n\
+            s1#1#Q\t.\texon\t110\t159\t.\t-\t.\tID=e3;Parent=n1\n");
+    }
+
+    #[test]
+    fn test_liftover_gene_models_split_sequences() {
+        // The second exon of g1 is deleted from s1#1#Q but found on s1#1#R, another sequence of the haplotype
+        let paf_data = "s1#1#Q\t140\t0\t140\t+\tT\t200\t0\t200\t140\t200\t60\tcg:Z:100=60D40=\n\
+                        s1#1#R\t60\t0\t60\t+\tT\t200\t100\t160\t60\t60\t60\tcg:Z:60=\n";
+        let gff = "##gff-version 3\n\
+                   T\t.\tgene\t41\t150\t.\t+\t.\tID=g1\n\
+                   T\t.\tmRNA\t41\t150\t.\t+\t.\tID=m1;Parent=g1\n\
+                   T\t.\texon\t46\t60\t.\t+\t.\tID=e1;Parent=m1\n\
+                   T\t.\tCDS\t46\t60\t.\t+\t0\tID=c1;Parent=m1\n\
+                   T\t.\texon\t111\t150\t.\t+\t.\tID=e2;Parent=m1\n\
+                   T\t.\tCDS\t111\t150\t.\t+\t0\tID=c2;Parent=m1\n";
+        let impg = test_impg("gene_models_split", paf_data);
+        let (mut lifted, mut unmapped) = (Vec::new(), Vec::new());
+        let stats = liftover_gene_models(&impg, BufReader::new(gff.as_bytes()), false, Some("s1#1#"), &mut lifted, &mut unmapped).unwrap();
+
+        assert_eq!((stats.lifted, stats.unmapped), (1, 0));
+        assert!(unmapped.is_empty());
+        assert_eq!(String::from_utf8(lifted).unwrap(), "##gff-version 3\n\
+            s1#1#Q\t.\tgene\t41\t100\t.\t+\t.\tID=g1;impg_flags=split_sequences\n\
+            s1#1#Q\t.\tmRNA\t41\t100\t.\t+\t.\tID=m1;Parent=g1\n\
+            s1#1#Q\t.\texon\t46\t60\t.\t+\t.\tID=e1;Parent=m1\n\
+            s1#1#Q\t.\tCDS\t46\t60\t.\t+\t0\tID=c1;Parent=m1\n\
+            s1#1#R\t.\texon\t11\t50\t.\t+\t.\tID=e2;Parent=m1\n\
+            s1#1#R\t.\tCDS\t11\t50\t.\t+\t0\tID=c2;Parent=m1\n");
+    }
+}
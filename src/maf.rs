@@ -0,0 +1,169 @@
+use std::io::{self, Write};
+use crate::faidx::FastaIndex;
+use crate::impg::{Impg, ProjectedInterval};
+use crate::paf::Strand;
+
+/// Lay out the results as a multiple alignment anchored on the target (a star alignment).
+///
+/// Each result is aligned against the target through its CIGAR, so bases aligned to the same
+/// target position share a column, and the insertions of every result get their own columns,
+/// gapped in all the other rows. `sequences[i]` is the sequence of `results[i]` in the
+/// orientation of the target. Returns one text row per result.
+pub fn star_alignment(results: &[ProjectedInterval], sequences: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let (block_start, block_end) = match block_range(results) {
+        Some(range) => range,
+        None => return Vec::new(),
+    };
+    let block_len = (block_end - block_start) as usize;
+
+    // Longest insertion before each target position (and after the last one)
+    let mut insertions = vec![0usize; block_len + 1];
+    for result in results {
+        let mut t = (result.target_start - block_start) as usize;
+        let mut pending = 0;
+        for op in &result.cigar {
+            if op.op() == 'I' {
+                pending += op.len() as usize;
+            } else {
+                insertions[t] = insertions[t].max(pending);
+                pending = 0;
+                t += op.target_delta() as usize;
+            }
+        }
+        insertions[t] = insertions[t].max(pending);
+    }
+
+    // Column of the first insertion slot before each target position
+    let mut slot_columns = Vec::with_capacity(block_len + 1);
+    let mut column = 0;
+    for (i, &insertion) in insertions.iter().enumerate() {
+        slot_columns.push(column);
+        column += insertion + usize::from(i < block_len);
+    }
+    let width = column;
+
+    results.iter().zip(sequences).map(|(result, sequence)| {
+        let mut text = vec![b'-'; width];
+        let mut t = (result.target_start - block_start) as usize;
+        let mut q = 0;
+        let mut inserted = 0;
+        let base = |q: usize| sequence.get(q).copied().unwrap_or(b'N');
+        for op in &result.cigar {
+            let len = op.len() as usize;
+            match op.op() {
+                'I' => {
+                    for k in 0..len {
+                        text[slot_columns[t] + inserted + k] = base(q + k);
+                    }
+                    inserted += len;
+                    q += len;
+                }
+                'D' => {
+                    t += len;
+                    inserted = 0;
+                }
+                _ => {
+                    for k in 0..len {
+                        text[slot_columns[t + k] + insertions[t + k]] = base(q + k);
+                    }
+                    t += len;
+                    q += len;
+                    inserted = 0;
+                }
+            }
+        }
+        text
+    }).collect()
+}
+
+/// Target range covered by the results
+fn block_range(results: &[ProjectedInterval]) -> Option<(i32, i32)> {
+    let start = results.iter().map(|r| r.target_start).min()?;
+    let end = results.iter().map(|r| r.target_end).max()?;
+    Some((start, end))
+}
+
+pub fn write_maf_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "##maf version=1")?;
+    writeln!(writer)
+}
+
+/// Write the results as a MAF alignment block, with their sequences fetched from the FASTA files.
+/// Results without query bases (falling in deletions) are left out.
+pub fn write_maf_block<W: Write>(writer: &mut W, impg: &Impg, results: &[ProjectedInterval], fasta_index: &FastaIndex) -> io::Result<()> {
+    let results: Vec<ProjectedInterval> = results.iter()
+        .filter(|result| result.query_start < result.query_end)
+        .cloned()
+        .collect();
+    let mut sequences = Vec::with_capacity(results.len());
+    for result in &results {
+        let query_name = impg.seq_index.get_name(result.query_id).unwrap();
+        sequences.push(fasta_index.fetch_oriented(query_name, result.query_start, result.query_end, result.strand)?);
+    }
+    let texts = star_alignment(&results, &sequences);
+    if texts.is_empty() {
+        return Ok(());
+    }
+
+    let name_width = results.iter().map(|r| impg.seq_index.get_name(r.query_id).unwrap().len()).max().unwrap_or(0);
+    writeln!(writer, "a")?;
+    for (result, text) in results.iter().zip(texts) {
+        let query_name = impg.seq_index.get_name(result.query_id).unwrap();
+        let query_len = impg.seq_index.get_len_from_id(result.query_id).unwrap() as i32;
+        // MAF coordinates of reverse strand rows are relative to the reverse complemented sequence
+        let (start, strand) = match result.strand {
+            Strand::Forward => (result.query_start, '+'),
+            Strand::Reverse => (query_len - result.query_end, '-'),
+        };
+        writeln!(
+            writer,
+            "s {:<name_width$} {:>10} {:>10} {} {:>10} {}",
+            query_name, start, result.query_end - result.query_start, strand, query_len, String::from_utf8_lossy(&text),
+        )?;
+    }
+    writeln!(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impg::CigarOp;
+
+    fn result(target_start: i32, target_end: i32, query_len: i32, cigar: &[(i32, char)]) -> ProjectedInterval {
+        ProjectedInterval {
+            query_start: 0,
+            query_end: query_len,
+            target_start,
+            target_end,
+            cigar: cigar.iter().map(|&(len, op)| CigarOp::new(len, op)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn texts(results: &[ProjectedInterval], sequences: &[&str]) -> Vec<String> {
+        let sequences: Vec<Vec<u8>> = sequences.iter().map(|s| s.as_bytes().to_vec()).collect();
+        star_alignment(results, &sequences).into_iter().map(|t| String::from_utf8(t).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_star_alignment_indels() {
+        let results = vec![
+            result(0, 8, 8, &[(8, '=')]),
+            result(0, 8, 8, &[(2, '='), (2, 'I'), (2, 'D'), (4, '=')]),
+            result(2, 6, 6, &[(1, '='), (1, 'I'), (2, 'X'), (1, 'I'), (1, '=')]),
+        ];
+        assert_eq!(
+            texts(&results, &["ACGTACGT", "ACttACGT", "GcTAgC"]),
+            vec!["AC--G-TA-CGT", "ACtt---A-CGT", "----GcTAgC--"]
+        );
+    }
+
+    #[test]
+    fn test_star_alignment_trailing_insertion() {
+        let results = vec![
+            result(0, 4, 4, &[(4, '=')]),
+            result(0, 4, 6, &[(4, '='), (2, 'I')]),
+        ];
+        assert_eq!(texts(&results, &["ACGT", "ACGTaa"]), vec!["ACGT--", "ACGTaa"]);
+    }
+}
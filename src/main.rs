@@ -1,17 +1,33 @@
-use clap::Parser;
+use clap::{ArgGroup, Parser};
 use std::fs::File;
-use std::io::{self, BufReader, BufWriter};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::num::NonZeroUsize;
 use noodles::bgzf;
-use impg::impg::{Impg, SerializableImpg, AdjustedInterval, check_intervals};
+use impg::impg::{Impg, ImpgError, PathCost, SerializableImpg, ProjectedInterval, TargetStats, INDEX_MAGIC, INDEX_VERSION, check_intervals};
 use coitrees::IntervalTree;
-use impg::paf;
+use impg::paf::{self, Strand};
+use impg::liftover::{self, FeatureFormat};
+use impg::faidx::{self, FastaIndex};
+use impg::closure;
+use impg::depth;
+use impg::gfa;
+use impg::maf;
+use impg::orthology::{self, OrthologyGroup};
+use impg::similarity;
+use impg::tree;
+use impg::partition;
+use impg::sv;
+use impg::vcf;
+use impg::seqidx::pansn_haplotype;
 use rayon::ThreadPoolBuilder;
+use rayon::prelude::*;
 use std::io::BufRead;
 
 /// Command-line tool for querying overlaps in PAF files.
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
+#[clap(group(ArgGroup::new("mode").multiple(false)))]
+#[clap(group(ArgGroup::new("output").multiple(false)))]
 struct Args {
     /// Path to the PAF file. If specified without an index, the tool will look for or generate an associated index file.
     #[clap(short='p', long, value_parser)]
@@ -33,9 +49,140 @@ struct Args {
     #[clap(short='x', long, action)]
     transitive: bool,
 
+    /// Split projections at insertions and deletions longer than this many bases, instead of spanning them.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    split_gap: Option<u32>,
+
     /// Output results in PAF format.
-    #[clap(short='P', long, action)]
+    #[clap(short='P', long, action, group = "output", conflicts_with_all = [
+        "structural_variants", "depth", "copy_number", "classify", "identity_matrix", "tree", "cluster",
+        "closure_graph", "orthologs", "partition", "liftover",
+    ])]
     output_paf: bool,
+
+    /// With -P, also report the alignment of each result against the sequence it was projected from (`ht:Z:` and `hc:Z:` tags).
+    #[clap(long, action)]
+    hops: bool,
+
+    /// Output the sequences of the results in FASTA format (requires --fasta-files).
+    #[clap(short='F', long, action, group = "output", conflicts_with = "mode")]
+    output_fasta: bool,
+
+    /// Output the results as a target-anchored multiple alignment in MAF format (requires --fasta-files).
+    #[clap(short='M', long, action, group = "output", conflicts_with = "mode")]
+    output_maf: bool,
+
+    /// Output the SNVs and indels of the results against the target range as a VCF with one column per PanSN haplotype (requires --fasta-files).
+    #[clap(long, action, group = "output", conflicts_with = "mode")]
+    output_vcf: bool,
+
+    /// Report structural variants (large indels, inversions and translocations) of the results against the target range in `vcf` or `bedpe` format (genome-wide without -r/-b).
+    #[clap(long, value_parser = ["vcf", "bedpe"], group = "mode")]
+    structural_variants: Option<String>,
+
+    /// Minimum size of the indels and inversions reported as structural variants.
+    #[clap(long, value_parser, default_value_t = 50)]
+    sv_min_size: i32,
+
+    /// Output the per-base depth of the results over the target range (the number of distinct aligned sequences, deletions excluded) as bedGraph (genome-wide without -r/-b).
+    #[clap(long, action, group = "mode")]
+    depth: bool,
+
+    /// With --depth, count distinct PanSN samples instead of sequences.
+    #[clap(long, action)]
+    depth_by_sample: bool,
+
+    /// Summarise the copies of the target range in each `sequence` or PanSN `sample` as TSV: the number of non-overlapping projected copies, the fraction of the target range they cover and their mean copy number (genome-wide without -r/-b).
+    #[clap(long, value_parser = ["sequence", "sample"], group = "mode")]
+    copy_number: Option<String>,
+
+    /// Classify the target range into core, shell, cloud and private regions by the PanSN samples aligned there, as BED with the class and the missing samples (genome-wide without -r/-b).
+    #[clap(long, action, group = "mode")]
+    classify: bool,
+
+    /// With --classify, regions present in at most this fraction of the samples (but more than one) are cloud rather than shell.
+    #[clap(long, value_parser, default_value_t = 0.15)]
+    cloud_fraction: f64,
+
+    /// Query sliding windows along a target, given as `seq_name` or `seq_name:start-end`, labelling the results by window id (BEDPE, or PAF with -P).
+    #[clap(long, value_parser, group = "mode", conflicts_with_all = ["target_range", "target_bed"])]
+    windows: Option<String>,
+
+    /// With --windows, the size of the windows.
+    #[clap(long, value_parser, default_value_t = 10000)]
+    window_size: i32,
+
+    /// With --windows, the distance between the starts of consecutive windows (the window size by default).
+    #[clap(long, value_parser)]
+    window_step: Option<i32>,
+
+    /// Output the pairwise identity matrix of the results of each target range, as `tsv` or as a `phylip` distance matrix.
+    #[clap(long, value_parser = ["tsv", "phylip"], group = "mode")]
+    identity_matrix: Option<String>,
+
+    /// Output a neighbor-joining tree (Newick) of the PanSN haplotypes aligned to each target range, from the distances of --identity-matrix.
+    #[clap(long, action, group = "mode")]
+    tree: bool,
+
+    /// Cluster the results of each target range into haplogroups, joining results with at least this identity (single linkage), and output the cluster of each result.
+    #[clap(long, value_parser, group = "mode")]
+    cluster: Option<f64>,
+
+    /// Output the graph of how the target range was projected across the index (use with -x), as `dot` or `json`: nodes are the result ranges, edges the alignments between them with their strand, identity and hop.
+    #[clap(long, value_parser = ["dot", "json"], group = "mode")]
+    closure_graph: Option<String>,
+
+    /// Project the target range only onto the sequences whose name starts with this prefix (e.g. `sample#1#`), through the best paths of alignments when they are not aligned to it directly.
+    #[clap(long, value_parser, group = "mode")]
+    path_to: Option<String>,
+
+    /// With --path-to, rank the paths by fewest `hops` or by highest `identity`.
+    #[clap(long, value_parser = ["hops", "identity"], default_value = "hops")]
+    path_cost: String,
+
+    /// Project each result back onto the target sequence and report the fraction landing in the target range (extra BED/BEDPE column, `rc:f:` PAF tag), `NA` for results whose sequence is not aligned to the target sequence.
+    #[clap(long, action, conflicts_with = "mode")]
+    reciprocal: bool,
+
+    /// Drop the results whose back-projection concordance (see --reciprocal) is below this value, e.g. to filter collapsed duplications.
+    #[clap(long, value_parser, conflicts_with = "mode")]
+    min_concordance: Option<f64>,
+
+    /// Report how much of the target range each result covers and how well, from its alignment: aligned target bases, their fraction of the target range, matches, mismatches, inserted and deleted bases, gap-compressed and BLAST identity (extra BED/BEDPE columns, `cv:i:`, `cf:f:`, `NM:i:`, `gi:f:` and `id:f:` PAF tags).
+    #[clap(long, action)]
+    alignment_stats: bool,
+
+    /// Keep one projection per PanSN `sample`, per `haplotype` or per `sequence`: the one covering the most target bases, then with the highest identity. Writes the range, strand, target range, covered target bases, identity and class (`ortholog`, or `paralog` for the other hits) of each result.
+    #[clap(long, value_parser = ["sample", "haplotype", "sequence"], group = "mode")]
+    orthologs: Option<String>,
+
+    /// With --orthologs, first merge the collinear fragments of each sequence separated by at most this many bases.
+    #[clap(long, value_parser)]
+    merge_collinear: Option<i32>,
+
+    /// Partition the whole pangenome by tiling the reference into windows of this size and projecting them transitively, writing one BED per partition.
+    #[clap(long, value_parser, group = "mode", conflicts_with_all = ["target_range", "target_bed"])]
+    partition: Option<i32>,
+
+    /// With --partition, the reference sequences to tile: all sequences whose name starts with this prefix (e.g. `CHM13#0#`).
+    #[clap(long, value_parser)]
+    partition_ref: Option<String>,
+
+    /// With --partition, the directory where the `partition<N>.bed` files are written.
+    #[clap(long, value_parser, default_value = ".")]
+    partition_dir: String,
+
+    /// Output a variation graph in GFA v1 induced from the alignments of the results, with one path per result (requires --fasta-files).
+    #[clap(long, action, group = "output", conflicts_with = "mode")]
+    output_gfa: bool,
+
+    /// Paths to faidx-indexed FASTA files (optionally bgzipped) containing the aligned sequences.
+    #[clap(short='f', long, value_parser, num_args = 1..)]
+    fasta_files: Vec<String>,
+
+    /// Report where each result comes from: the PAF record (0-based line index), the index of the result it was projected from and the hop depth (extra BED/BEDPE columns, `ai:i:`, `pi:i:` and `hd:i:` PAF tags).
+    #[clap(long, action)]
+    provenance: bool,
         
     /// Print stats about the index.
     #[clap(short='s', long, action)]
@@ -46,8 +193,24 @@ struct Args {
     num_threads: NonZeroUsize,
 
     /// Check the projected intervals, reporting the wrong ones (slow, useful for debugging).
-    #[clap(short='c', long, action)]
+    #[clap(short='c', long, action, conflicts_with = "mode")]
     check_intervals: bool,
+
+    /// Path to a BED/GFF3/GTF/VCF file to lift over onto the aligned sequences (use -x to lift through the whole index).
+    #[clap(short='l', long, value_parser, group = "mode", conflicts_with_all = ["target_range", "target_bed"])]
+    liftover: Option<String>,
+
+    /// Only lift over onto sequences whose name starts with this prefix (e.g. `sample#1#`).
+    #[clap(long, value_parser)]
+    liftover_to: Option<String>,
+
+    /// Path where features that could not be lifted over are written, each preceded by a `#reason` line.
+    #[clap(long, value_parser)]
+    unmapped: Option<String>,
+
+    /// Lift over whole GFF3 gene models, keeping their hierarchy and flagging inconsistent projections.
+    #[clap(long, action)]
+    gene_models: bool,
 }
 
 fn main() -> io::Result<()> {
@@ -66,9 +229,129 @@ fn main() -> io::Result<()> {
         print_stats(&impg);
     }
 
+    let fasta_index = if args.fasta_files.is_empty() {
+        None
+    } else {
+        Some(FastaIndex::from_paths(&args.fasta_files)?)
+    };
+    if (args.output_fasta || args.output_maf || args.output_vcf || args.output_gfa) && fasta_index.is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "FASTA, MAF, VCF and GFA output require --fasta-files"));
+    }
+    if args.output_maf && (args.target_range.is_some() || args.target_bed.is_some()) {
+        maf::write_maf_header(&mut io::stdout().lock())?;
+    }
+
+    if let Some(liftover_file) = &args.liftover {
+        return run_liftover(&impg, liftover_file, args.liftover_to.as_deref(), args.unmapped.as_deref(), args.gene_models, args.transitive);
+    }
+
+    if let Some(format) = &args.structural_variants {
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        return run_structural_variants(&impg, &targets, format, args.sv_min_size, args.transitive, args.split_gap);
+    }
+
+    if let Some(format) = &args.closure_graph {
+        if args.target_range.is_none() && args.target_bed.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "--closure-graph requires -r or -b"));
+        }
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        return run_closure_graph(&impg, &targets, format, args.transitive, args.split_gap);
+    }
+
+    if let Some(prefix) = &args.path_to {
+        if args.target_range.is_none() && args.target_bed.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "--path-to requires -r or -b"));
+        }
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        let cost = if args.path_cost == "identity" { PathCost::Identity } else { PathCost::Hops };
+        return run_path(&impg, &targets, prefix, cost, args.output_paf, args.hops, args.provenance);
+    }
+
+    if let Some(by) = &args.orthologs {
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        let by = match by.as_str() {
+            "sample" => OrthologyGroup::Sample,
+            "haplotype" => OrthologyGroup::Haplotype,
+            _ => OrthologyGroup::Sequence,
+        };
+        return run_orthologs(&impg, &targets, by, args.merge_collinear, args.transitive, args.split_gap);
+    }
+
+    if let Some(window_size) = args.partition {
+        let reference_prefix = args.partition_ref.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "--partition requires --partition-ref")
+        })?;
+        if window_size <= 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Partition window size must be positive"));
+        }
+        return run_partition(&impg, reference_prefix, window_size, &args.partition_dir);
+    }
+
+    if let Some(format) = &args.identity_matrix {
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        return run_identity_matrix(&impg, &targets, format, args.transitive, args.split_gap);
+    }
+
+    if args.tree {
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        return run_tree(&impg, &targets, args.transitive, args.split_gap);
+    }
+
+    if let Some(min_identity) = args.cluster {
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        return run_cluster(&impg, &targets, min_identity, args.transitive, args.split_gap);
+    }
+
+    if args.depth {
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        return run_depth(&impg, &targets, args.depth_by_sample, args.transitive, args.split_gap);
+    }
+
+    if let Some(by) = &args.copy_number {
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        return run_copy_number(&impg, &targets, by == "sample", args.transitive, args.split_gap);
+    }
+
+    if args.classify {
+        let targets = query_targets(&impg, args.target_range.as_deref(), args.target_bed.as_deref())?;
+        return run_classify(&impg, &targets, args.cloud_fraction, args.transitive, args.split_gap);
+    }
+
+    if let Some(windows) = &args.windows {
+        let (target_name, target_range) = match windows.rsplit_once(':') {
+            Some(_) => parse_target_range(windows)?,
+            None => {
+                let target_id = impg.seq_index.get_id(windows).ok_or_else(|| ImpgError::UnknownSequenceName(windows.to_string()))?;
+                (windows.to_string(), (0, impg.seq_index.get_len_from_id(target_id).unwrap() as i32))
+            }
+        };
+        let window_step = args.window_step.unwrap_or(args.window_size);
+        if args.window_size <= 0 || window_step <= 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Window size and step must be positive"));
+        }
+        let batch_size = usize::from(args.num_threads) * 16;
+        let windows = partition::sliding_windows(target_range, args.window_size, window_step);
+        for (batch_index, batch) in windows.chunks(batch_size).enumerate() {
+            let batch_results = batch.par_iter()
+                .map(|&window| perform_query(&impg, &target_name, window, args.transitive, args.split_gap))
+                .collect::<io::Result<Vec<_>>>()?;
+            for (i, results) in batch_results.into_iter().enumerate() {
+                let name = Some(format!("window{}", batch_index * batch_size + i));
+                let annotations = Annotations { concordance: None, stats_range: args.alignment_stats.then_some(batch[i]) };
+                if args.output_paf {
+                    output_results_paf(&impg, results, args.hops, args.provenance, &annotations, &target_name, name);
+                } else {
+                    output_results_bedpe(&impg, results, args.provenance, &annotations, &target_name, name);
+                }
+            }
+        }
+        return Ok(());
+    }
+
     if let Some(target_range) = args.target_range {
         let (target_name, target_range) = parse_target_range(&target_range)?;
-        let results = perform_query(&impg, &target_name, target_range, args.transitive);
+        let vcf_haplotypes = if args.output_vcf { write_vcf_header(&impg, &[&target_name])? } else { Vec::new() };
+        let results = perform_query(&impg, &target_name, target_range, args.transitive, args.split_gap)?;
         if args.check_intervals {
             let invalid_cigars = check_intervals(&impg, &results);
             if !invalid_cigars.is_empty() {
@@ -78,15 +361,35 @@ fn main() -> io::Result<()> {
                 panic!("Invalid intervals encountered.");
             }
         }
-        if args.output_paf {
-            output_results_paf(&impg, results, &target_name, None);
+        let (results, concordance) = reciprocal_filter(&impg, &target_name, target_range, results, args.reciprocal, args.min_concordance)?;
+        if let (true, Some(fasta_index)) = (args.output_fasta, &fasta_index) {
+            output_results_fasta(&impg, &results, fasta_index)?;
+        } else if let (true, Some(fasta_index)) = (args.output_maf, &fasta_index) {
+            maf::write_maf_block(&mut BufWriter::new(io::stdout().lock()), &impg, &results, fasta_index)?;
+        } else if let (true, Some(fasta_index)) = (args.output_gfa, &fasta_index) {
+            let target_id = impg.seq_index.get_id(&target_name).unwrap();
+            gfa::write_region_gfa(&mut BufWriter::new(io::stdout().lock()), &impg, target_id, &results, fasta_index)?;
+        } else if let (true, Some(fasta_index)) = (args.output_vcf, &fasta_index) {
+            let target_id = impg.seq_index.get_id(&target_name).unwrap();
+            let haplotypes: Vec<&str> = vcf_haplotypes.iter().map(String::as_str).collect();
+            vcf::write_vcf_records(&mut BufWriter::new(io::stdout().lock()), &impg, target_id, &results, fasta_index, &haplotypes)?;
+        } else if args.output_paf {
+            let annotations = Annotations { concordance: concordance.as_deref(), stats_range: args.alignment_stats.then_some(target_range) };
+            output_results_paf(&impg, results, args.hops, args.provenance, &annotations, &target_name, None);
         } else {
-            output_results_bed(&impg, results);
+            let annotations = Annotations { concordance: concordance.as_deref(), stats_range: args.alignment_stats.then_some(target_range) };
+            output_results_bed(&impg, results, args.provenance, &annotations);
         }
     } else if let Some(target_bed) = args.target_bed {
         let targets = parse_bed_file(&target_bed)?;
+        let vcf_haplotypes = if args.output_vcf {
+            let target_names: Vec<&str> = targets.iter().map(|(target_name, _, _)| target_name.as_str()).collect();
+            write_vcf_header(&impg, &target_names)?
+        } else {
+            Vec::new()
+        };
         for (target_name, target_range, name) in targets {
-            let results = perform_query(&impg, &target_name, target_range, args.transitive);
+            let results = perform_query(&impg, &target_name, target_range, args.transitive, args.split_gap)?;
             if args.check_intervals {
                 let invalid_cigars = check_intervals(&impg, &results);
                 if !invalid_cigars.is_empty() {
@@ -96,17 +399,33 @@ fn main() -> io::Result<()> {
                     panic!("Invalid intervals encountered.");
                 }
             }
-            if args.output_paf {
-                output_results_paf(&impg, results, &target_name, name);
+            let (results, concordance) = reciprocal_filter(&impg, &target_name, target_range, results, args.reciprocal, args.min_concordance)?;
+            if let (true, Some(fasta_index)) = (args.output_fasta, &fasta_index) {
+                output_results_fasta(&impg, &results, fasta_index)?;
+            } else if let (true, Some(fasta_index)) = (args.output_maf, &fasta_index) {
+                maf::write_maf_block(&mut BufWriter::new(io::stdout().lock()), &impg, &results, fasta_index)?;
+            } else if let (true, Some(fasta_index)) = (args.output_gfa, &fasta_index) {
+                let target_id = impg.seq_index.get_id(&target_name).unwrap();
+                gfa::write_region_gfa(&mut BufWriter::new(io::stdout().lock()), &impg, target_id, &results, fasta_index)?;
+            } else if let (true, Some(fasta_index)) = (args.output_vcf, &fasta_index) {
+                let target_id = impg.seq_index.get_id(&target_name).unwrap();
+                let haplotypes: Vec<&str> = vcf_haplotypes.iter().map(String::as_str).collect();
+                vcf::write_vcf_records(&mut BufWriter::new(io::stdout().lock()), &impg, target_id, &results, fasta_index, &haplotypes)?;
+            } else if args.output_paf {
+                let annotations = Annotations { concordance: concordance.as_deref(), stats_range: args.alignment_stats.then_some(target_range) };
+                output_results_paf(&impg, results, args.hops, args.provenance, &annotations, &target_name, name);
             } else {
-                output_results_bedpe(&impg, results, &target_name, name);
+                let annotations = Annotations { concordance: concordance.as_deref(), stats_range: args.alignment_stats.then_some(target_range) };
+                output_results_bedpe(&impg, results, args.provenance, &annotations, &target_name, name);
             }
         }
     }
     Ok(())
 }
 
-fn parse_bed_file(bed_file: &str) -> io::Result<Vec<(String, (i32, i32), Option<String>)>> {
+type BedRange = (String, (i32, i32), Option<String>);
+
+fn parse_bed_file(bed_file: &str) -> io::Result<Vec<BedRange>> {
     let file = File::open(bed_file)?;
     let reader = BufReader::new(file);
     let mut ranges = Vec::new();
@@ -154,7 +473,13 @@ fn parse_range(range_parts: &[&str]) -> io::Result<(i32, i32)> {
 fn load_or_generate_index(paf_file: &str, num_threads: NonZeroUsize) -> io::Result<Impg> {
     let index_file = format!("{}.impg", paf_file);
     if std::path::Path::new(&index_file).exists() {
-        load_index(paf_file)
+        match load_index(paf_file)? {
+            Some(impg) => Ok(impg),
+            None => {
+                eprintln!("WARNING:\tIndex {} was created by an incompatible version of impg, regenerating it.", index_file);
+                generate_index(paf_file, num_threads)
+            }
+        }
     } else {
         generate_index(paf_file, num_threads)
     }
@@ -169,18 +494,21 @@ fn generate_index(paf_file: &str, num_threads: NonZeroUsize) -> io::Result<Impg>
     };
     let reader = BufReader::new(reader);
     let records = paf::parse_paf(reader).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to parse PAF records: {:?}", e)))?;
-    let impg = Impg::from_paf_records(&records, paf_file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to create index: {:?}", e)))?;
+    let impg = Impg::from_paf_records(&records, paf_file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to create index: {}", e)))?;
 
     let index_file = format!("{}.impg", paf_file);
     let serializable = impg.to_serializable();
     let file = File::create(index_file)?;
-    let writer = BufWriter::new(file);
-    bincode::serialize_into(writer, &serializable).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to serialize index: {:?}", e)))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&INDEX_MAGIC)?;
+    writer.write_all(&INDEX_VERSION.to_le_bytes())?;
+    bincode::serialize_into(writer, &serializable).map_err(|e| io::Error::other(format!("Failed to serialize index: {:?}", e)))?;
 
     Ok(impg)
 }
 
-fn load_index(paf_file: &str) -> io::Result<Impg> {
+/// Load the index of a PAF file, or `None` if it has an older or unknown format
+fn load_index(paf_file: &str) -> io::Result<Option<Impg>> {
     let index_file = format!("{}.impg", paf_file);
     
     let paf_file_metadata = std::fs::metadata(paf_file)?;
@@ -195,98 +523,429 @@ fn load_index(paf_file: &str) -> io::Result<Impg> {
     }
 
     let file = File::open(index_file)?;
-    let reader = BufReader::new(file);
+    let mut reader = BufReader::new(file);
+    let mut header = [0; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) if header[..4] == INDEX_MAGIC && header[4..] == INDEX_VERSION.to_le_bytes() => {}
+        Ok(()) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
     let serializable: SerializableImpg = bincode::deserialize_from(reader).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to deserialize index: {:?}", e)))?;
-    Ok(Impg::from_paf_and_serializable(paf_file, serializable))
+    Ok(Some(Impg::from_paf_and_serializable(paf_file, serializable)?))
 }
 
-fn perform_query(impg: &Impg, target_name: &str, target_range: (i32, i32), transitive: bool) -> Vec<AdjustedInterval> {
+fn perform_query(impg: &Impg, target_name: &str, target_range: (i32, i32), transitive: bool, split_gap: Option<u32>) -> io::Result<Vec<ProjectedInterval>> {
     let (target_start, target_end) = target_range;
-    let target_id = impg.seq_index.get_id(target_name).expect("Target name not found in index");
-    let target_length = impg.seq_index.get_len_from_id(target_id).expect("Target length not found in index");
-    if target_end > target_length as i32 {
-        panic!("Target range end ({}) exceeds the target sequence length ({})", target_end, target_length);
+    let target_id = impg.seq_index.get_id(target_name).ok_or_else(|| ImpgError::UnknownSequenceName(target_name.to_string()))?;
+    let results = if transitive {
+        impg.project_transitive(target_id, target_start, target_end, split_gap)?
+    } else {
+        impg.project(target_id, target_start, target_end, split_gap)?
+    };
+    Ok(results)
+}
+
+fn strand_char(strand: Strand) -> char {
+    match strand {
+        Strand::Forward => '+',
+        Strand::Reverse => '-',
+    }
+}
+
+/// Back-projection concordance of each result, `None` when it could not be projected back
+type Concordance = Vec<Option<f64>>;
+
+/// Back-projection concordance of each result (`--reciprocal`), dropping the results below `min_concordance`.
+/// Returns no concordance if neither is requested.
+fn reciprocal_filter(impg: &Impg, target_name: &str, target_range: (i32, i32), results: Vec<ProjectedInterval>, reciprocal: bool, min_concordance: Option<f64>) -> io::Result<(Vec<ProjectedInterval>, Option<Concordance>)> {
+    if !reciprocal && min_concordance.is_none() {
+        return Ok((results, None));
+    }
+    let target_id = impg.seq_index.get_id(target_name).unwrap();
+    let (start, end) = target_range;
+    let concordance = results.par_iter()
+        .map(|result| impg.reciprocal_concordance(result, target_id, start, end))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (results, concordance): (Vec<ProjectedInterval>, Concordance) = results.into_iter().zip(concordance)
+        .filter(|(_, concordance)| match (concordance, min_concordance) {
+            (Some(concordance), Some(min_concordance)) => *concordance >= min_concordance,
+            _ => true,
+        })
+        .unzip();
+    Ok((results, Some(concordance)))
+}
+
+/// Optional annotations of the results in BED, BEDPE and PAF output
+#[derive(Default)]
+struct Annotations<'a> {
+    /// Back-projection concordance of each result (`--reciprocal`)
+    concordance: Option<&'a [Option<f64>]>,
+    /// Target range the coverage of the results is relative to (`--alignment-stats`)
+    stats_range: Option<(i32, i32)>,
+}
+
+impl Annotations<'_> {
+    /// Coverage of the stats range by the result, with the stats of its alignment
+    fn stats(&self, result: &ProjectedInterval) -> Option<TargetStats> {
+        let (start, end) = self.stats_range?;
+        Some(TargetStats::new(result, start, end))
     }
-    if transitive {
-        impg.query_transitive(target_id, target_start, target_end)
+
+    /// Extra BED/BEDPE columns of the i-th result
+    fn columns(&self, i: usize, result: &ProjectedInterval) -> String {
+        let mut columns = String::new();
+        if let Some(concordance) = self.concordance {
+            match concordance[i] {
+                Some(value) => columns.push_str(&format!("\t{:.6}", value)),
+                None => columns.push_str("\tNA"),
+            }
+        }
+        if let Some(stats) = self.stats(result) {
+            columns.push_str(&stats.columns());
+        }
+        columns
+    }
+
+    /// Extra PAF tags of the i-th result
+    fn tags(&self, i: usize, result: &ProjectedInterval) -> String {
+        let mut tags = String::new();
+        if let Some(Some(concordance)) = self.concordance.map(|concordance| concordance[i]) {
+            tags.push_str(&format!("\trc:f:{:.6}", concordance));
+        }
+        if let Some(stats) = self.stats(result) {
+            tags.push_str(&stats.tags());
+        }
+        tags
+    }
+}
+
+/// Extra BED/BEDPE columns with the PAF record, parent result and hop depth of the result (`--provenance`)
+fn provenance_columns(result: &ProjectedInterval, show_provenance: bool) -> String {
+    if !show_provenance {
+        return String::new();
+    }
+    let provenance = &result.provenance;
+    format!("\t{}\t{}\t{}",
+            provenance.alignment_id.map_or(".".to_string(), |id| id.to_string()),
+            provenance.parent.map_or(".".to_string(), |parent| parent.to_string()),
+            provenance.depth)
+}
+
+fn output_results_bed(impg: &Impg, results: Vec<ProjectedInterval>, show_provenance: bool, annotations: &Annotations) {
+    for (i, result) in results.into_iter().enumerate() {
+        let overlap_name = impg.seq_index.get_name(result.query_id).unwrap();
+        println!("{}\t{}\t{}\t.\t{}{}{}", overlap_name, result.query_start, result.query_end, strand_char(result.strand),
+                 provenance_columns(&result, show_provenance), annotations.columns(i, &result));
+    }
+}
+
+fn output_results_bedpe(impg: &Impg, results: Vec<ProjectedInterval>, show_provenance: bool, annotations: &Annotations, target_name: &str, name: Option<String>) {
+    for (i, result) in results.into_iter().enumerate() {
+        let overlap_name = impg.seq_index.get_name(result.query_id).unwrap();
+        let provenance_columns = provenance_columns(&result, show_provenance);
+        println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t0\t{}\t+{}{}",
+                 overlap_name, result.query_start, result.query_end,
+                 target_name, result.target_start, result.target_end,
+                 name.as_deref().unwrap_or("."), strand_char(result.strand), provenance_columns,
+                 annotations.columns(i, &result));
+    }
+}
+
+fn output_results_paf(impg: &Impg, results: Vec<ProjectedInterval>, hops: bool, show_provenance: bool, annotations: &Annotations, target_name: &str, name: Option<String>) { 
+    let target_length = impg.seq_index.get_len_from_id(impg.seq_index.get_id(target_name).unwrap()).unwrap();  
+    for (i, result) in results.into_iter().enumerate() {
+        let overlap_name = impg.seq_index.get_name(result.query_id).unwrap();
+        let (first, last, strand) = (result.query_start, result.query_end, strand_char(result.strand));
+        let (target_first, target_last) = (result.target_start, result.target_end);
+
+        let query_length = impg.seq_index.get_len_from_id(result.query_id).unwrap();  
+
+        // Matches are overestimated by counting all M operations
+        let stats = result.stats();
+        let (matches, block_len) = (stats.matches, stats.block_len());
+        let cigar_str : String = result.cigar.iter().map(|op| format!("{}{}", op.len(), op.op())).collect();
+        let mut extra_tags = String::new();
+        let provenance = &result.provenance;
+        if show_provenance {
+            if let Some(alignment_id) = provenance.alignment_id {
+                extra_tags.push_str(&format!("\tai:i:{}", alignment_id));
+            }
+            if let Some(parent) = provenance.parent {
+                extra_tags.push_str(&format!("\tpi:i:{}", parent));
+            }
+            extra_tags.push_str(&format!("\thd:i:{}", provenance.depth));
+        }
+        extra_tags.push_str(&annotations.tags(i, &result));
+        if hops {
+            extra_tags.push_str(&format!("\tht:Z:{}:{}-{}\thc:Z:{}",
+                                         impg.seq_index.get_name(provenance.hop_target_id).unwrap(), provenance.hop_target_start, provenance.hop_target_end,
+                                         provenance.hop_cigar.iter().map(|op| format!("{}{}", op.len(), op.op())).collect::<String>()));
+        }
+
+        match name {
+            Some(ref name) => println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tcg:Z:{}\tan:Z:{}{}",
+                                    overlap_name, query_length, first, last, strand,
+                                    target_name, target_length, target_first, target_last,
+                                    matches, block_len, 255, cigar_str, name, extra_tags),
+            None => println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tcg:Z:{}{}",
+                                overlap_name, query_length, first, last, strand,
+                                target_name, target_length, target_first, target_last,
+                                matches, block_len, 255, cigar_str, extra_tags),
+        }
+    }
+}
+
+fn run_liftover(impg: &Impg, liftover_file: &str, liftover_to: Option<&str>, unmapped_file: Option<&str>, gene_models: bool, transitive: bool) -> io::Result<()> {
+    let format = FeatureFormat::from_path(liftover_file).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Liftover file must end in .bed, .gff3, .gff, .gtf or .vcf"))?;
+    if gene_models && format != FeatureFormat::Gff3 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Gene models can only be lifted over from GFF3 files"));
+    }
+    let reader = BufReader::new(File::open(liftover_file)?);
+    let mut lifted = BufWriter::new(io::stdout().lock());
+    let mut unmapped: Box<dyn io::Write> = match unmapped_file {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::sink()),
+    };
+
+    let stats = if gene_models {
+        liftover::liftover_gene_models(impg, reader, transitive, liftover_to, &mut lifted, &mut unmapped)?
+    } else {
+        liftover::liftover(impg, reader, format, transitive, liftover_to, &mut lifted, &mut unmapped)?
+    };
+    let unit = if gene_models { "gene models" } else { "features" };
+    eprintln!("Lifted over {} {}, {} unmapped", stats.lifted, unit, stats.unmapped);
+    Ok(())
+}
+
+/// Write the VCF header for the target sequences and return its haplotype columns: all the
+/// haplotypes in the index except those of the targets
+fn write_vcf_header(impg: &Impg, target_names: &[&str]) -> io::Result<Vec<String>> {
+    let target_haplotypes: Vec<&str> = target_names.iter().map(|name| pansn_haplotype(name)).collect();
+    let haplotypes: Vec<&str> = impg.seq_index.haplotypes().into_iter()
+        .filter(|haplotype| !target_haplotypes.contains(haplotype))
+        .collect();
+    let mut contigs: Vec<(&str, usize)> = Vec::new();
+    for &name in target_names {
+        if !contigs.iter().any(|(contig, _)| *contig == name) {
+            let length = impg.seq_index.get_id(name).and_then(|id| impg.seq_index.get_len_from_id(id)).unwrap_or(0);
+            contigs.push((name, length));
+        }
+    }
+    vcf::write_vcf_header(&mut io::stdout().lock(), &contigs, &[], &haplotypes)?;
+    Ok(haplotypes.into_iter().map(String::from).collect())
+}
+
+/// Target ranges of a query: the -r range, the -b ranges, or whole target sequences when neither is given
+fn query_targets(impg: &Impg, target_range: Option<&str>, target_bed: Option<&str>) -> io::Result<Vec<BedRange>> {
+    if let Some(target_range) = target_range {
+        let (target_name, range) = parse_target_range(target_range)?;
+        Ok(vec![(target_name, range, None)])
+    } else if let Some(target_bed) = target_bed {
+        parse_bed_file(target_bed)
     } else {
-        impg.query(target_id, target_start, target_end)
+        let mut targets: Vec<BedRange> = impg.trees.keys().map(|&target_id| {
+            let target_name = impg.seq_index.get_name(target_id).unwrap().to_string();
+            let target_len = impg.seq_index.get_len_from_id(target_id).unwrap() as i32;
+            (target_name, (0, target_len), None)
+        }).collect();
+        targets.sort();
+        Ok(targets)
     }
 }
 
-fn output_results_bed(impg: &Impg, results: Vec<AdjustedInterval>) {
-    for (overlap, _, _) in results {
-        let overlap_name = impg.seq_index.get_name(overlap.metadata).unwrap();
-        let (first, last, strand) = if overlap.first <= overlap.last {
-            (overlap.first, overlap.last, '+')
+fn run_structural_variants(impg: &Impg, targets: &[BedRange], format: &str, min_size: i32, transitive: bool, split_gap: Option<u32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    let haplotypes: Vec<&str> = impg.seq_index.haplotypes();
+    if format == "vcf" {
+        let mut contigs: Vec<(&str, usize)> = Vec::new();
+        for (target_name, _, _) in targets {
+            if !contigs.iter().any(|(contig, _)| contig == target_name) {
+                let length = impg.seq_index.get_id(target_name).and_then(|id| impg.seq_index.get_len_from_id(id)).unwrap_or(0);
+                contigs.push((target_name, length));
+            }
+        }
+        vcf::write_vcf_header(&mut writer, &contigs, sv::VCF_META, &haplotypes)?;
+    }
+    for (target_name, target_range, _) in targets {
+        let results = perform_query(impg, target_name, *target_range, transitive, split_gap)?;
+        let variants = sv::detect_structural_variants(impg, &results, min_size);
+        if format == "vcf" {
+            sv::write_vcf(&mut writer, impg, target_name, &variants, &results, &haplotypes)?;
         } else {
-            (overlap.last, overlap.first, '-')
-        };
-        println!("{}\t{}\t{}\t.\t{}", overlap_name, first, last, strand);
+            sv::write_bedpe(&mut writer, impg, target_name, &variants)?;
+        }
     }
+    writer.flush()
 }
 
-fn output_results_bedpe(impg: &Impg, results: Vec<AdjustedInterval>, target_name: &str, name: Option<String>) {
-    for (overlap_query, _, overlap_target) in results {
-        let overlap_name = impg.seq_index.get_name(overlap_query.metadata).unwrap();
-        let (first, last, strand) = if overlap_query.first <= overlap_query.last {
-            (overlap_query.first, overlap_query.last, '+')
+fn run_closure_graph(impg: &Impg, targets: &[BedRange], format: &str, transitive: bool, split_gap: Option<u32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for (target_name, (start, end), name) in targets {
+        let results = perform_query(impg, target_name, (*start, *end), transitive, split_gap)?;
+        let region = name.clone().unwrap_or_else(|| format!("{}:{}-{}", target_name, start, end));
+        if format == "json" {
+            closure::write_json(&mut writer, impg, &region, &results)?;
         } else {
-            (overlap_query.last, overlap_query.first, '-')
-        };
-        println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t0\t{}\t+",
-                 overlap_name, first, last,
-                 target_name, overlap_target.first, overlap_target.last,
-                 name.as_deref().unwrap_or("."), strand);
+            closure::write_dot(&mut writer, impg, &region, &results)?;
+        }
     }
+    writer.flush()
 }
 
-fn output_results_paf(impg: &Impg, results: Vec<AdjustedInterval>, target_name: &str, name: Option<String>) { 
-    let target_length = impg.seq_index.get_len_from_id(impg.seq_index.get_id(target_name).unwrap()).unwrap();  
-    for (overlap_query, cigar, overlap_target) in results {
-        let overlap_name = impg.seq_index.get_name(overlap_query.metadata).unwrap();
-        let (first, last, strand) = if overlap_query.first <= overlap_query.last {
-            (overlap_query.first, overlap_query.last, '+')
+fn run_path(impg: &Impg, targets: &[BedRange], prefix: &str, cost: PathCost, output_paf: bool, hops: bool, provenance: bool) -> io::Result<()> {
+    let destination_ids: Vec<u32> = (0..impg.seq_index.len() as u32)
+        .filter(|&id| impg.seq_index.get_name(id).is_some_and(|name| name.starts_with(prefix)))
+        .collect();
+    if destination_ids.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No sequence starts with {}", prefix)));
+    }
+    for (target_name, (start, end), name) in targets {
+        let target_id = impg.seq_index.get_id(target_name).ok_or_else(|| ImpgError::UnknownSequenceName(target_name.to_string()))?;
+        let results = impg.project_path(target_id, *start, *end, &destination_ids, cost)?;
+        if output_paf {
+            output_results_paf(impg, results, hops, provenance, &Annotations::default(), target_name, name.clone());
         } else {
-            (overlap_query.last, overlap_query.first, '-')
-        };
+            output_results_bedpe(impg, results, provenance, &Annotations::default(), target_name, name.clone());
+        }
+    }
+    Ok(())
+}
 
-        let query_length = impg.seq_index.get_len_from_id(overlap_query.metadata).unwrap();  
-
-        let has_m_operation = cigar.iter().any(|op| op.op() == 'M');
-        let (matches, block_len) = if has_m_operation {
-            // We overestimate the number of matches by counting all M operations
-            cigar.iter().fold((0, 0), |(matches, block_len), op| {
-                let len = op.len();
-                match op.op() {
-                    'M' => (matches + len, block_len + len),
-                    'I' | 'D' => (matches, block_len + len),
-                    _ => (matches, block_len),
-                }
-            })
+fn run_partition(impg: &Impg, reference_prefix: &str, window_size: i32, partition_dir: &str) -> io::Result<()> {
+    let mut reference_ids: Vec<u32> = (0..impg.seq_index.len() as u32)
+        .filter(|&id| impg.seq_index.get_name(id).is_some_and(|name| name.starts_with(reference_prefix)))
+        .collect();
+    if reference_ids.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No sequence starts with {}", reference_prefix)));
+    }
+    reference_ids.sort_by_key(|&id| impg.seq_index.get_name(id));
+
+    std::fs::create_dir_all(partition_dir)?;
+    let count = partition::partition(impg, &reference_ids, window_size, |index, ranges| {
+        let path = std::path::Path::new(partition_dir).join(format!("partition{}.bed", index));
+        let mut writer = BufWriter::new(File::create(path)?);
+        for (seq_id, start, end) in ranges {
+            writeln!(writer, "{}\t{}\t{}", impg.seq_index.get_name(seq_id).unwrap(), start, end)?;
+        }
+        writer.flush()
+    })?;
+    eprintln!("Wrote {} partitions to {}", count, partition_dir);
+    Ok(())
+}
+
+fn run_identity_matrix(impg: &Impg, targets: &[BedRange], format: &str, transitive: bool, split_gap: Option<u32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for (target_name, (start, end), name) in targets {
+        let results = perform_query(impg, target_name, (*start, *end), transitive, split_gap)?;
+        let (results, matrix) = similarity::identity_matrix(impg, &results)?;
+        let labels: Vec<String> = results.iter().map(|result| similarity::label(impg, result)).collect();
+        if format == "phylip" {
+            similarity::write_phylip(&mut writer, &labels, &matrix)?;
         } else {
-            cigar.iter().fold((0, 0), |(matches, block_len), op| {
-                let len = op.len();
-                match op.op() {
-                    '=' => (matches + len, block_len + len),
-                    'X' | 'I' | 'D' => (matches, block_len + len),
-                    _ => (matches, block_len),
-                }
-            })
-        };
-        let cigar_str : String = cigar.iter().map(|op| format!("{}{}", op.len(), op.op())).collect();
+            let region = name.clone().unwrap_or_else(|| format!("{}:{}-{}", target_name, start, end));
+            similarity::write_tsv(&mut writer, &region, &labels, &matrix)?;
+        }
+    }
+    writer.flush()
+}
 
-        match name {
-            Some(ref name) => println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tcg:Z:{}\tan:Z:{}",
-                                    overlap_name, query_length, first, last, strand,
-                                    target_name, target_length, overlap_target.first, overlap_target.last,
-                                    matches, block_len, 255, cigar_str, name),
-            None => println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tcg:Z:{}",
-                                overlap_name, query_length, first, last, strand,
-                                target_name, target_length, overlap_target.first, overlap_target.last,
-                                matches, block_len, 255, cigar_str),
+fn run_tree(impg: &Impg, targets: &[BedRange], transitive: bool, split_gap: Option<u32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for (target_name, (start, end), _) in targets {
+        let results = perform_query(impg, target_name, (*start, *end), transitive, split_gap)?;
+        let (results, matrix) = similarity::identity_matrix(impg, &results)?;
+        let (labels, distances) = tree::haplotype_distances(impg, &results, &matrix);
+        writeln!(writer, "{}", tree::neighbor_joining(&labels, &distances))?;
+    }
+    writer.flush()
+}
+
+fn run_cluster(impg: &Impg, targets: &[BedRange], min_identity: f64, transitive: bool, split_gap: Option<u32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for (target_name, (start, end), name) in targets {
+        let results = perform_query(impg, target_name, (*start, *end), transitive, split_gap)?;
+        let (results, matrix) = similarity::identity_matrix(impg, &results)?;
+        let region = name.clone().unwrap_or_else(|| format!("{}:{}-{}", target_name, start, end));
+        for (result, cluster) in results.iter().zip(similarity::cluster(&matrix, min_identity)) {
+            writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}",
+                     impg.seq_index.get_name(result.query_id).unwrap(), result.query_start, result.query_end,
+                     strand_char(result.strand), region, cluster)?;
+        }
+    }
+    writer.flush()
+}
+
+fn run_orthologs(impg: &Impg, targets: &[BedRange], by: OrthologyGroup, merge_collinear: Option<i32>, transitive: bool, split_gap: Option<u32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for (target_name, (start, end), name) in targets {
+        let mut results = perform_query(impg, target_name, (*start, *end), transitive, split_gap)?;
+        if let Some(max_gap) = merge_collinear {
+            results = orthology::merge_collinear(&results, max_gap);
+        }
+        let region = name.clone().unwrap_or_else(|| format!("{}:{}-{}", target_name, start, end));
+        let (orthologs, paralogs) = orthology::one_to_one(impg, &results, by);
+        let classes = orthologs.iter().map(|r| (r, "ortholog")).chain(paralogs.iter().map(|r| (r, "paralog")));
+        for (result, class) in classes {
+            let covered: i32 = result.aligned_target_ranges().iter().map(|(s, e)| e - s).sum();
+            writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}\t{:.6}\t{}",
+                     impg.seq_index.get_name(result.query_id).unwrap(), result.query_start, result.query_end,
+                     strand_char(result.strand), region, covered, result.stats().identity(), class)?;
+        }
+    }
+    writer.flush()
+}
+
+fn run_depth(impg: &Impg, targets: &[BedRange], by_sample: bool, transitive: bool, split_gap: Option<u32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for (target_name, (start, end), _) in targets {
+        let results = perform_query(impg, target_name, (*start, *end), transitive, split_gap)?;
+        for (run_start, run_end, depth) in depth::target_depth(impg, &results, *start, *end, by_sample) {
+            writeln!(writer, "{}\t{}\t{}\t{}", target_name, run_start, run_end, depth)?;
+        }
+    }
+    writer.flush()
+}
+
+fn run_copy_number(impg: &Impg, targets: &[BedRange], by_sample: bool, transitive: bool, split_gap: Option<u32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    writeln!(writer, "#region\t{}\tcopies\tcovered_fraction\tmean_copy_number", if by_sample { "sample" } else { "sequence" })?;
+    for (target_name, (start, end), name) in targets {
+        let results = perform_query(impg, target_name, (*start, *end), transitive, split_gap)?;
+        let region = name.clone().unwrap_or_else(|| format!("{}:{}-{}", target_name, start, end));
+        for (group, copy_number) in depth::copy_number(impg, &results, *start, *end, by_sample) {
+            writeln!(writer, "{}\t{}\t{}\t{:.6}\t{:.6}", region, group, copy_number.copies,
+                     copy_number.covered_fraction, copy_number.mean_copy_number)?;
+        }
+    }
+    writer.flush()
+}
+
+fn run_classify(impg: &Impg, targets: &[BedRange], cloud_fraction: f64, transitive: bool, split_gap: Option<u32>) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for (target_name, (start, end), _) in targets {
+        let results = perform_query(impg, target_name, (*start, *end), transitive, split_gap)?;
+        for (run_start, run_end, class, missing) in depth::classify_target(impg, &results, *start, *end, cloud_fraction) {
+            let missing = if missing.is_empty() { ".".to_string() } else { missing.join(",") };
+            writeln!(writer, "{}\t{}\t{}\t{}\t{}", target_name, run_start, run_end, class, missing)?;
+        }
+    }
+    writer.flush()
+}
+
+fn output_results_fasta(impg: &Impg, results: &[ProjectedInterval], fasta_index: &FastaIndex) -> io::Result<()> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    for result in results {
+        // Ranges falling entirely in deletions have no sequence
+        if result.query_start == result.query_end {
+            continue;
         }
+        let query_name = impg.seq_index.get_name(result.query_id).unwrap();
+        let sequence = fasta_index.fetch_oriented(query_name, result.query_start, result.query_end, result.strand)?;
+        let record_name = format!("{}:{}-{}({})", query_name, result.query_start, result.query_end, strand_char(result.strand));
+        faidx::write_fasta_record(&mut writer, &record_name, &sequence, 80)?;
     }
+    writer.flush()
 }
 
 fn print_stats(impg: &Impg) {
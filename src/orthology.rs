@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use crate::impg::{CigarOp, Impg, ProjectedInterval};
+use crate::paf::Strand;
+use crate::seqidx::{pansn_haplotype, pansn_sample};
+
+/// Append an operation to a CIGAR, extending the last operation when it is of the same type
+fn push_op(cigar: &mut Vec<CigarOp>, len: i32, op: char) {
+    if len == 0 {
+        return;
+    }
+    match cigar.last_mut() {
+        Some(last) if last.op() == op => *last = CigarOp::new(last.len() + len, op),
+        _ => cigar.push(CigarOp::new(len, op)),
+    }
+}
+
+/// Merge the collinear fragments of each query sequence: consecutive results on the same strand whose
+/// target and query ranges follow each other, separated by at most `max_gap` bases on both. The gaps are
+/// added to the alignment as deletions and insertions. Merged results keep the provenance of their
+/// first fragment (in target order) and the position of their earliest one in the results.
+pub fn merge_collinear(results: &[ProjectedInterval], max_gap: i32) -> Vec<ProjectedInterval> {
+    let mut fragments: HashMap<(u32, Strand), Vec<usize>> = HashMap::new();
+    for (i, result) in results.iter().enumerate() {
+        fragments.entry((result.query_id, result.strand)).or_default().push(i);
+    }
+
+    let mut merged: Vec<(usize, ProjectedInterval)> = Vec::new();
+    for (_, mut indexes) in fragments {
+        indexes.sort_by_key(|&i| (results[i].target_start, results[i].target_end));
+        let mut current: Option<(usize, ProjectedInterval)> = None;
+        for i in indexes {
+            let next = &results[i];
+            if let Some((ref mut first, ref mut cur)) = current {
+                let target_gap = next.target_start - cur.target_end;
+                let query_gap = match next.strand {
+                    Strand::Forward => next.query_start - cur.query_end,
+                    Strand::Reverse => cur.query_start - next.query_end,
+                };
+                if (0..=max_gap).contains(&target_gap) && (0..=max_gap).contains(&query_gap) {
+                    push_op(&mut cur.cigar, target_gap, 'D');
+                    push_op(&mut cur.cigar, query_gap, 'I');
+                    for op in &next.cigar {
+                        push_op(&mut cur.cigar, op.len(), op.op());
+                    }
+                    cur.query_start = cur.query_start.min(next.query_start);
+                    cur.query_end = cur.query_end.max(next.query_end);
+                    cur.target_end = next.target_end;
+                    *first = (*first).min(i);
+                    continue;
+                }
+            }
+            merged.extend(current.replace((i, next.clone())));
+        }
+        merged.extend(current);
+    }
+    merged.sort_by_key(|(i, _)| *i);
+    merged.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Whether each result is the best of its group, by aligned target bases then identity (ties go to the
+/// first result)
+fn best_of_groups(results: &[ProjectedInterval], groups: &[&str]) -> Vec<bool> {
+    let mut best: HashMap<&str, (usize, i32, f64)> = HashMap::new();
+    for (i, (result, &group)) in results.iter().zip(groups).enumerate() {
+        let covered = result.aligned_target_ranges().iter().map(|(start, end)| end - start).sum();
+        let identity = result.stats().identity();
+        let entry = best.entry(group).or_insert((i, covered, identity));
+        if (covered, identity) > (entry.1, entry.2) {
+            *entry = (i, covered, identity);
+        }
+    }
+    (0..results.len()).map(|i| best[groups[i]].0 == i).collect()
+}
+
+/// What one-to-one orthologs are picked for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrthologyGroup {
+    /// PanSN samples (`sample`)
+    Sample,
+    /// PanSN haplotypes (`sample#haplotype`)
+    Haplotype,
+    Sequence,
+}
+
+/// Split the results into orthologs, the best result of each group, and the paralogs and secondary hits left.
+/// The input range is not a candidate, so the other haplotypes of the target's sample get their own orthologs.
+pub fn one_to_one(impg: &Impg, results: &[ProjectedInterval], by: OrthologyGroup) -> (Vec<ProjectedInterval>, Vec<ProjectedInterval>) {
+    let results: Vec<ProjectedInterval> = results.iter()
+        .filter(|result| result.provenance.alignment_id.is_some())
+        .cloned()
+        .collect();
+    let groups: Vec<&str> = results.iter().map(|result| {
+        let name = impg.seq_index.get_name(result.query_id).unwrap();
+        match by {
+            OrthologyGroup::Sample => pansn_sample(name),
+            OrthologyGroup::Haplotype => pansn_haplotype(name),
+            OrthologyGroup::Sequence => name,
+        }
+    }).collect();
+    let (mut orthologs, mut paralogs) = (Vec::new(), Vec::new());
+    for (result, best) in results.iter().zip(best_of_groups(&results, &groups)) {
+        if best {
+            orthologs.push(result.clone());
+        } else {
+            paralogs.push(result.clone());
+        }
+    }
+    (orthologs, paralogs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_impg;
+
+    fn result(query_id: u32, query: (i32, i32), target: (i32, i32), strand: Strand, cigar: &[(i32, char)]) -> ProjectedInterval {
+        ProjectedInterval {
+            query_id,
+            query_start: query.0,
+            query_end: query.1,
+            target_start: target.0,
+            target_end: target.1,
+            strand,
+            cigar: cigar.iter().map(|&(len, op)| CigarOp::new(len, op)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_collinear() {
+        let results = vec![
+            result(1, (120, 150), (20, 50), Strand::Forward, &[(30, '=')]),
+            result(2, (60, 100), (0, 40), Strand::Reverse, &[(40, '=')]),
+            result(1, (100, 110), (0, 10), Strand::Forward, &[(10, '=')]),
+            result(2, (0, 40), (45, 85), Strand::Reverse, &[(40, '=')]),
+            result(1, (500, 520), (60, 80), Strand::Forward, &[(20, '=')]),
+        ];
+        // The query gap between the fragments of 2 is too large
+        let merged = merge_collinear(&results, 10);
+        assert_eq!(merged.len(), 4);
+        assert_eq!((merged[0].query_start, merged[0].query_end, merged[0].target_start, merged[0].target_end), (100, 150, 0, 50));
+        assert_eq!(merged[0].cigar, vec![CigarOp::new(10, '='), CigarOp::new(10, 'D'), CigarOp::new(10, 'I'), CigarOp::new(30, '=')]);
+        assert_eq!((merged[1].query_start, merged[1].query_end), (60, 100));
+        assert_eq!((merged[2].query_start, merged[2].query_end), (0, 40));
+        assert_eq!((merged[3].query_start, merged[3].query_end), (500, 520));
+    }
+
+    #[test]
+    fn test_merge_collinear_reverse() {
+        let results = vec![
+            result(2, (60, 100), (0, 40), Strand::Reverse, &[(40, '=')]),
+            result(2, (0, 55), (45, 100), Strand::Reverse, &[(55, '=')]),
+        ];
+        let merged = merge_collinear(&results, 10);
+        assert_eq!(merged.len(), 1);
+        assert_eq!((merged[0].query_start, merged[0].query_end, merged[0].target_start, merged[0].target_end), (0, 100, 0, 100));
+        assert_eq!(merged[0].cigar, vec![CigarOp::new(40, '='), CigarOp::new(5, 'D'), CigarOp::new(5, 'I'), CigarOp::new(55, '=')]);
+    }
+
+    #[test]
+    fn test_best_of_groups() {
+        let results = vec![
+            result(1, (0, 50), (0, 50), Strand::Forward, &[(50, '=')]),
+            result(2, (0, 80), (0, 80), Strand::Forward, &[(79, '='), (1, 'X')]),
+            result(3, (0, 80), (0, 80), Strand::Forward, &[(80, '=')]),
+            result(4, (0, 10), (0, 10), Strand::Forward, &[(10, '=')]),
+        ];
+        assert_eq!(best_of_groups(&results, &["a", "a", "a", "b"]), vec![false, false, true, true]);
+        assert_eq!(best_of_groups(&results, &["a", "a", "c", "a"]), vec![false, true, true, false]);
+    }
+
+    #[test]
+    fn test_one_to_one() {
+        // s1#2 is the other haplotype of the target's sample, with a second, shorter copy
+        let paf_data = "s1#2#Q\t300\t0\t100\t+\ts1#1#T\t100\t0\t100\t100\t100\t60\tcg:Z:100=\n\
+                        s1#2#Q\t300\t200\t250\t+\ts1#1#T\t100\t20\t70\t50\t50\t60\tcg:Z:50=\n\
+                        s2#1#R\t100\t0\t90\t-\ts1#1#T\t100\t10\t100\t90\t90\t60\tcg:Z:90=\n";
+        let impg = test_impg("orthology", paf_data);
+        let target_id = impg.seq_index.get_id("s1#1#T").unwrap();
+        let results = impg.project(target_id, 0, 100, None).unwrap();
+        let ranges = |results: &[ProjectedInterval]| -> Vec<(&str, i32, i32)> {
+            let mut ranges: Vec<_> = results.iter()
+                .map(|r| (impg.seq_index.get_name(r.query_id).unwrap(), r.query_start, r.query_end))
+                .collect();
+            ranges.sort();
+            ranges
+        };
+
+        // The input range does not take the ortholog of s1
+        let (orthologs, paralogs) = one_to_one(&impg, &results, OrthologyGroup::Sample);
+        assert_eq!(ranges(&orthologs), vec![("s1#2#Q", 0, 100), ("s2#1#R", 0, 90)]);
+        assert_eq!(ranges(&paralogs), vec![("s1#2#Q", 200, 250)]);
+
+        let (orthologs, paralogs) = one_to_one(&impg, &results, OrthologyGroup::Haplotype);
+        assert_eq!(ranges(&orthologs), vec![("s1#2#Q", 0, 100), ("s2#1#R", 0, 90)]);
+        assert_eq!(paralogs.len(), 1);
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use crate::impg::{Impg, ImpgError};
+
+/// Sequence ranges `(seq_id, start, end)` making up a partition
+pub type Partition = Vec<(u32, i32, i32)>;
+
+/// Parts of `[start, end)` not covered by the sorted, disjoint `ranges`
+fn subtract(ranges: &[(i32, i32)], start: i32, end: i32) -> Vec<(i32, i32)> {
+    let mut parts = Vec::new();
+    let mut position = start;
+    for &(s, e) in ranges {
+        if e <= position {
+            continue;
+        }
+        if s >= end {
+            break;
+        }
+        if s > position {
+            parts.push((position, s));
+        }
+        position = position.max(e);
+    }
+    if position < end {
+        parts.push((position, end));
+    }
+    parts
+}
+
+/// Add `[start, end)` to the sorted, disjoint `ranges`, merging overlapping and adjacent ones
+fn insert(ranges: &mut Vec<(i32, i32)>, start: i32, end: i32) {
+    let first = ranges.partition_point(|&(_, e)| e < start);
+    let mut last = first;
+    let (mut new_start, mut new_end) = (start, end);
+    while last < ranges.len() && ranges[last].0 <= end {
+        new_start = new_start.min(ranges[last].0);
+        new_end = new_end.max(ranges[last].1);
+        last += 1;
+    }
+    ranges.splice(first..last, [(new_start, new_end)]);
+}
+
+/// Windows of `size` bases starting every `step` bases along `[start, end)`, up to the first one reaching
+/// the end, which is shorter when the range is not a whole number of steps
+pub fn sliding_windows(range: (i32, i32), size: i32, step: i32) -> Vec<(i32, i32)> {
+    let (start, end) = range;
+    let mut windows = Vec::new();
+    let mut window_start = start;
+    while window_start < end {
+        let window_end = (window_start + size).min(end);
+        windows.push((window_start, window_end));
+        if window_end == end {
+            break;
+        }
+        window_start += step;
+    }
+    windows
+}
+
+/// Split the implicit pangenome into partitions, assigning every base of every sequence to exactly one.
+///
+/// The reference sequences are tiled into windows of `window_size` bases, and each window is projected
+/// transitively: the projected ranges not yet assigned to a previous partition form a new partition.
+/// Ranges unreachable from the reference are then tiled in the same way, seeding new partitions, until
+/// all sequences are covered. Sequences that are targets of alignments seed before query-only ones,
+/// which cannot reach the sequences they are aligned to. Each partition is passed to `emit` with its (0-based) index as soon as
+/// it is complete. Returns the number of partitions. Window sizes below 1 are taken as 1.
+pub fn partition<F>(impg: &Impg, reference_ids: &[u32], window_size: i32, mut emit: F) -> Result<usize, ImpgError>
+where
+    F: FnMut(usize, Partition) -> std::io::Result<()>,
+{
+    let window_size = window_size.max(1);
+    let mut assigned: HashMap<u32, Vec<(i32, i32)>> = HashMap::new();
+    let mut count = 0;
+
+    let mut other_ids: Vec<u32> = (0..impg.seq_index.len() as u32)
+        .filter(|id| !reference_ids.contains(id))
+        .collect();
+    other_ids.sort_by_key(|&id| (!impg.trees.contains_key(&id), impg.seq_index.get_name(id)));
+
+    for &seq_id in reference_ids.iter().chain(other_ids.iter()) {
+        let seq_len = impg.seq_index.get_len_from_id(seq_id).ok_or(ImpgError::UnknownSequenceId(seq_id))? as i32;
+        for (window_start, window_end) in sliding_windows((0, seq_len), window_size, window_size) {
+            let seeds = subtract(assigned.get(&seq_id).map_or(&[][..], |r| r.as_slice()), window_start, window_end);
+            if seeds.is_empty() {
+                continue;
+            }
+
+            let mut partition: Partition = Vec::new();
+            for (seed_start, seed_end) in seeds {
+                for result in impg.project_transitive(seq_id, seed_start, seed_end, None)? {
+                    let ranges = assigned.entry(result.query_id).or_default();
+                    for (start, end) in subtract(ranges, result.query_start, result.query_end) {
+                        insert(ranges, start, end);
+                        partition.push((result.query_id, start, end));
+                    }
+                }
+            }
+            if partition.is_empty() {
+                continue;
+            }
+            partition.sort_by_key(|&(id, start, end)| (impg.seq_index.get_name(id), start, end));
+            emit(count, partition)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_impg;
+
+    #[test]
+    fn test_sliding_windows() {
+        // Overlapping windows stop at the first one reaching the end
+        assert_eq!(sliding_windows((0, 25), 10, 5), vec![(0, 10), (5, 15), (10, 20), (15, 25)]);
+        assert_eq!(sliding_windows((0, 30), 10, 10), vec![(0, 10), (10, 20), (20, 30)]);
+        // The remainder is kept as a shorter window
+        assert_eq!(sliding_windows((100, 125), 10, 10), vec![(100, 110), (110, 120), (120, 125)]);
+        assert_eq!(sliding_windows((0, 7), 10, 10), vec![(0, 7)]);
+        assert_eq!(sliding_windows((0, 25), 5, 10), vec![(0, 5), (10, 15), (20, 25)]);
+    }
+
+    #[test]
+    fn test_subtract() {
+        let ranges = vec![(10, 20), (30, 40)];
+        assert_eq!(subtract(&ranges, 0, 50), vec![(0, 10), (20, 30), (40, 50)]);
+        assert_eq!(subtract(&ranges, 15, 35), vec![(20, 30)]);
+        assert_eq!(subtract(&ranges, 12, 18), vec![]);
+        assert_eq!(subtract(&[], 5, 8), vec![(5, 8)]);
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut ranges = vec![(10, 20), (30, 40)];
+        insert(&mut ranges, 50, 60);
+        insert(&mut ranges, 0, 5);
+        assert_eq!(ranges, vec![(0, 5), (10, 20), (30, 40), (50, 60)]);
+        insert(&mut ranges, 20, 30);
+        assert_eq!(ranges, vec![(0, 5), (10, 40), (50, 60)]);
+        insert(&mut ranges, 3, 55);
+        assert_eq!(ranges, vec![(0, 60)]);
+    }
+
+    #[test]
+    fn test_partition() {
+        // R#0#1 is the reference; E is only reachable through B, and F and G only to each other
+        let paf_data = "A\t100\t0\t100\t+\tR#0#1\t250\t0\t100\t100\t100\t60\tcg:Z:100=\n\
+                        B\t80\t0\t80\t-\tR#0#1\t250\t120\t200\t80\t80\t60\tcg:Z:40=5I35=5D\n\
+                        E\t60\t10\t40\t+\tB\t80\t50\t80\t30\t30\t60\tcg:Z:30=\n\
+                        F\t30\t0\t10\t+\tG\t10\t0\t10\t10\t10\t60\tcg:Z:10=\n";
+        let impg = test_impg("partition", paf_data);
+        let reference_id = impg.seq_index.get_id("R#0#1").unwrap();
+        let mut partitions = Vec::new();
+        let count = partition(&impg, &[reference_id], 50, |index, ranges| {
+            assert_eq!(index, partitions.len());
+            partitions.push(ranges);
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(count, partitions.len());
+        assert!(partitions.iter().all(|ranges| !ranges.is_empty()));
+        // Every base of every sequence is in exactly one partition
+        let mut ranges_by_seq: HashMap<u32, Vec<(i32, i32)>> = HashMap::new();
+        for &(seq_id, start, end) in partitions.iter().flatten() {
+            assert!(start < end);
+            ranges_by_seq.entry(seq_id).or_default().push((start, end));
+        }
+        assert_eq!(ranges_by_seq.len(), impg.seq_index.len());
+        for (seq_id, mut ranges) in ranges_by_seq {
+            ranges.sort();
+            assert!(ranges.windows(2).all(|pair| pair[0].1 <= pair[1].0), "overlapping ranges on {}", seq_id);
+            let covered: i32 = ranges.iter().map(|(start, end)| end - start).sum();
+            assert_eq!((ranges[0].0, covered), (0, impg.seq_index.get_len_from_id(seq_id).unwrap() as i32));
+        }
+
+        // Aligned ranges are in the same partition, even when the query sorts before the target
+        let partition_of = |name: &str, start: i32, end: i32| {
+            let seq_id = impg.seq_index.get_id(name).unwrap();
+            partitions.iter().position(|ranges| ranges.contains(&(seq_id, start, end)))
+        };
+        assert!(partition_of("G", 0, 10).is_some());
+        assert_eq!(partition_of("F", 0, 10), partition_of("G", 0, 10));
+        assert_eq!(partition_of("F", 10, 30).map(|i| partitions[i].len()), Some(1));
+    }
+}
@@ -53,4 +53,38 @@ impl SequenceIndex {
     pub fn len(&self) -> usize {
         self.name_to_id.len()
     }
+
+    /// Sorted, deduplicated PanSN haplotypes (`sample#haplotype`) of all the sequences
+    pub fn haplotypes(&self) -> Vec<&str> {
+        let mut haplotypes: Vec<&str> = self.id_to_name.values().map(|name| pansn_haplotype(name)).collect();
+        haplotypes.sort_unstable();
+        haplotypes.dedup();
+        haplotypes
+    }
+
+    /// Sorted, deduplicated PanSN samples of all the sequences
+    pub fn samples(&self) -> Vec<&str> {
+        let mut samples: Vec<&str> = self.id_to_name.values().map(|name| pansn_sample(name)).collect();
+        samples.sort_unstable();
+        samples.dedup();
+        samples
+    }
+}
+
+/// Return the `sample#haplotype` prefix of a PanSN sequence name (`sample#haplotype#contig`),
+/// or the whole name if it does not follow the PanSN convention.
+pub fn pansn_haplotype(name: &str) -> &str {
+    match name.rfind('#') {
+        Some(pos) => &name[..pos],
+        None => name,
+    }
+}
+
+/// Return the sample of a PanSN sequence name (`sample#haplotype#contig`),
+/// or the whole name if it does not follow the PanSN convention.
+pub fn pansn_sample(name: &str) -> &str {
+    match name.find('#') {
+        Some(pos) => &name[..pos],
+        None => name,
+    }
 }
@@ -0,0 +1,187 @@
+use std::io::{self, Write};
+use crate::impg::{compose_alignments, invert_alignment, AlignmentStats, Impg, ImpgError, ProjectedInterval};
+use crate::paf::Strand;
+
+/// Square matrix of pairwise identities, `None` for pairs without overlap
+pub type IdentityMatrix = Vec<Vec<Option<f64>>>;
+
+/// Identity between two results from their alignments against the target: both are clipped to the
+/// target range they share, and the alignment of `b` against `a` is composed through the target.
+/// Returns `None` if they do not overlap on the target.
+pub fn composed_identity(a: &ProjectedInterval, b: &ProjectedInterval) -> Option<f64> {
+    let (start, end) = (a.target_start.max(b.target_start), a.target_end.min(b.target_end));
+    if start >= end {
+        return None;
+    }
+    let a = a.clip_to_target(start, end);
+    let b = b.clip_to_target(start, end);
+    let (_, cigar, _) = compose_alignments(&invert_alignment(&a.to_adjusted()), &b.to_adjusted());
+    let stats = AlignmentStats::from_cigar(&cigar);
+    (stats.block_len() > 0).then(|| stats.identity())
+}
+
+/// Identity of the direct alignment (among `projections` of `a`) with the most overlap with `b`, if any
+fn direct_identity(projections: &[ProjectedInterval], b: &ProjectedInterval) -> Option<f64> {
+    projections.iter()
+        .filter(|p| p.provenance.alignment_id.is_some() && p.query_id == b.query_id)
+        .map(|p| (p.query_end.min(b.query_end) - p.query_start.max(b.query_start), p))
+        .filter(|(overlap, _)| *overlap > 0)
+        .max_by_key(|(overlap, _)| *overlap)
+        .map(|(_, p)| p.stats().identity())
+}
+
+/// Pairwise identity between all the results with query bases. Direct alignments between two results
+/// are used when the index has them; otherwise their alignments are composed through the target.
+/// Returns the results the matrix is about and the matrix.
+pub fn identity_matrix(impg: &Impg, results: &[ProjectedInterval]) -> Result<(Vec<ProjectedInterval>, IdentityMatrix), ImpgError> {
+    let results: Vec<ProjectedInterval> = results.iter()
+        .filter(|r| r.query_start < r.query_end)
+        .cloned()
+        .collect();
+    let projections = results.iter()
+        .map(|r| impg.project(r.query_id, r.query_start, r.query_end, None))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let n = results.len();
+    let mut matrix = vec![vec![None; n]; n];
+    for i in 0..n {
+        matrix[i][i] = Some(1.0);
+        for j in i + 1..n {
+            let identity = direct_identity(&projections[i], &results[j])
+                .or_else(|| direct_identity(&projections[j], &results[i]))
+                .or_else(|| composed_identity(&results[i], &results[j]));
+            matrix[i][j] = identity;
+            matrix[j][i] = identity;
+        }
+    }
+    Ok((results, matrix))
+}
+
+/// Single-linkage clustering: results are in the same cluster when they are connected by pairs with
+/// at least `min_identity`. Returns the cluster of each result, clusters being numbered from 0 by
+/// decreasing size (then by their first result).
+pub fn cluster(matrix: &IdentityMatrix, min_identity: f64) -> Vec<usize> {
+    let n = matrix.len();
+    let mut parent: Vec<usize> = (0..n).collect();
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (i, row) in matrix.iter().enumerate() {
+        for (j, identity) in row.iter().enumerate().skip(i + 1) {
+            if identity.is_some_and(|identity| identity >= min_identity) {
+                let (root_i, root_j) = (find(&mut parent, i), find(&mut parent, j));
+                parent[root_i.max(root_j)] = root_i.min(root_j);
+            }
+        }
+    }
+
+    let roots: Vec<usize> = (0..n).map(|i| find(&mut parent, i)).collect();
+    let mut clusters: Vec<(usize, usize)> = Vec::new(); // (root, size), in order of first result
+    for &root in &roots {
+        match clusters.iter_mut().find(|(r, _)| *r == root) {
+            Some(cluster) => cluster.1 += 1,
+            None => clusters.push((root, 1)),
+        }
+    }
+    let mut order: Vec<usize> = (0..clusters.len()).collect();
+    order.sort_by_key(|&c| std::cmp::Reverse(clusters[c].1));
+    roots.iter().map(|root| {
+        order.iter().position(|&c| clusters[c].0 == *root).unwrap()
+    }).collect()
+}
+
+/// Label of a result in a matrix, `seq_name:start-end(strand)`
+pub fn label(impg: &Impg, result: &ProjectedInterval) -> String {
+    let strand = if result.strand == Strand::Forward { '+' } else { '-' };
+    format!("{}:{}-{}({})", impg.seq_index.get_name(result.query_id).unwrap(), result.query_start, result.query_end, strand)
+}
+
+/// Write the identity matrix as TSV with a header row starting with the `region` name, `NA` marking
+/// pairs without overlap
+pub fn write_tsv<W: Write>(writer: &mut W, region: &str, labels: &[String], matrix: &[Vec<Option<f64>>]) -> io::Result<()> {
+    writeln!(writer, "{}\t{}", region, labels.join("\t"))?;
+    for (label, row) in labels.iter().zip(matrix) {
+        write!(writer, "{}", label)?;
+        for identity in row {
+            match identity {
+                Some(identity) => write!(writer, "\t{:.6}", identity)?,
+                None => write!(writer, "\tNA")?,
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Write the distance matrix (1 - identity) in relaxed PHYLIP format, pairs without overlap being at distance 1
+pub fn write_phylip<W: Write>(writer: &mut W, labels: &[String], matrix: &[Vec<Option<f64>>]) -> io::Result<()> {
+    writeln!(writer, "{}", labels.len())?;
+    for (label, row) in labels.iter().zip(matrix) {
+        write!(writer, "{}", label)?;
+        for identity in row {
+            write!(writer, " {:.6}", 1.0 - identity.unwrap_or(0.0))?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impg::CigarOp;
+
+    fn result(target_start: i32, target_end: i32, query_len: i32, strand: Strand, cigar: &[(i32, char)]) -> ProjectedInterval {
+        ProjectedInterval {
+            query_start: 0,
+            query_end: query_len,
+            target_start,
+            target_end,
+            strand,
+            cigar: cigar.iter().map(|&(len, op)| CigarOp::new(len, op)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_composed_identity() {
+        let a = result(0, 100, 100, Strand::Forward, &[(50, '='), (1, 'X'), (49, '=')]);
+        let b = result(0, 100, 98, Strand::Reverse, &[(20, '='), (2, 'D'), (78, '=')]);
+        // 97 matches, 1 mismatch and 2 deleted bases over 100 columns
+        assert_eq!(composed_identity(&a, &b), Some(0.97));
+        assert_eq!(composed_identity(&b, &a), Some(0.97));
+    }
+
+    #[test]
+    fn test_composed_identity_partial_overlap() {
+        let a = result(0, 50, 50, Strand::Forward, &[(50, '=')]);
+        let b = result(40, 90, 50, Strand::Forward, &[(5, 'X'), (45, '=')]);
+        assert_eq!(composed_identity(&a, &b), Some(0.5));
+        let c = result(60, 90, 30, Strand::Forward, &[(30, '=')]);
+        assert_eq!(composed_identity(&a, &c), None);
+    }
+
+    #[test]
+    fn test_cluster() {
+        let matrix = vec![
+            vec![Some(1.0), Some(0.95), None, Some(0.5)],
+            vec![Some(0.95), Some(1.0), Some(0.5), Some(0.99)],
+            vec![None, Some(0.5), Some(1.0), Some(0.5)],
+            vec![Some(0.5), Some(0.99), Some(0.5), Some(1.0)],
+        ];
+        assert_eq!(cluster(&matrix, 0.9), vec![0, 0, 1, 0]);
+        assert_eq!(cluster(&matrix, 0.98), vec![1, 0, 2, 0]);
+    }
+
+    #[test]
+    fn test_write_phylip() {
+        let mut out = Vec::new();
+        let labels = vec!["a".to_string(), "b".to_string()];
+        write_phylip(&mut out, &labels, &[vec![Some(1.0), None], vec![None, Some(1.0)]]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "2\na 0.000000 1.000000\nb 1.000000 0.000000\n");
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+use crate::impg::{Impg, ProjectedInterval};
+use crate::paf::Strand;
+use crate::seqidx::pansn_haplotype;
+
+/// Extra VCF header lines for structural variants
+pub const VCF_META: &[&str] = &[
+    "ALT=<ID=DEL,Description=\"Deletion\">",
+    "ALT=<ID=INS,Description=\"Insertion\">",
+    "ALT=<ID=INV,Description=\"Inversion\">",
+    "ALT=<ID=TRA,Description=\"Translocation\">",
+    "INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type of structural variant\">",
+    "INFO=<ID=END,Number=1,Type=Integer,Description=\"End position of the variant on the target\">",
+    "INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length of the variant\">",
+    "INFO=<ID=CHR2,Number=1,Type=String,Description=\"Query sequence joined at the translocation\">",
+    "INFO=<ID=POS2,Number=1,Type=Integer,Description=\"Query position joined at the translocation\">",
+    "INFO=<ID=SUPP,Number=1,Type=Integer,Description=\"Number of supporting haplotypes\">",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SvType {
+    Deletion,
+    Insertion,
+    Inversion,
+    Translocation,
+}
+
+impl fmt::Display for SvType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SvType::Deletion => write!(f, "DEL"),
+            SvType::Insertion => write!(f, "INS"),
+            SvType::Inversion => write!(f, "INV"),
+            SvType::Translocation => write!(f, "TRA"),
+        }
+    }
+}
+
+/// A structural variant of a query against the target
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructuralVariant {
+    pub sv_type: SvType,
+    /// Affected target range (empty for insertions and translocation breakpoints)
+    pub target_start: i32,
+    pub target_end: i32,
+    pub query_id: u32,
+    /// Affected query range (empty for deletions and translocation breakpoints)
+    pub query_start: i32,
+    pub query_end: i32,
+    pub strand: Strand,
+}
+
+impl StructuralVariant {
+    pub fn len(&self) -> i32 {
+        match self.sv_type {
+            SvType::Insertion => self.query_end - self.query_start,
+            _ => self.target_end - self.target_start,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Variants called at the same target range (and with the same length) by different genomes are the same event
+    fn event_key(&self) -> (i32, i32, SvType, i32) {
+        (self.target_start, self.target_end, self.sv_type, self.len())
+    }
+}
+
+/// Insertions and deletions of at least `min_size` bases in the CIGAR of a projected interval
+pub fn cigar_indels(result: &ProjectedInterval, min_size: i32) -> Vec<StructuralVariant> {
+    let mut variants = Vec::new();
+    let mut t = result.target_start;
+    // Query position in target order, decreasing on the reverse strand
+    let mut q = match result.strand {
+        Strand::Forward => result.query_start,
+        Strand::Reverse => result.query_end,
+    };
+    for op in &result.cigar {
+        let query_delta = op.query_delta(result.strand);
+        let sv_type = match op.op() {
+            'I' => Some(SvType::Insertion),
+            'D' => Some(SvType::Deletion),
+            _ => None,
+        };
+        if let Some(sv_type) = sv_type.filter(|_| op.len() >= min_size) {
+            variants.push(StructuralVariant {
+                sv_type,
+                target_start: t,
+                target_end: t + op.target_delta(),
+                query_id: result.query_id,
+                query_start: q.min(q + query_delta),
+                query_end: q.max(q + query_delta),
+                strand: result.strand,
+            });
+        }
+        t += op.target_delta();
+        q += query_delta;
+    }
+    variants
+}
+
+/// Breaks between the projections of each haplotype: projections on the minority strand of their
+/// query sequence spanning at least `min_size` target bases are inversions, and consecutive
+/// projections (along the target) on different query sequences are translocations.
+pub fn alignment_breaks(impg: &Impg, results: &[&ProjectedInterval], min_size: i32) -> Vec<StructuralVariant> {
+    let mut variants = Vec::new();
+
+    let mut strand_bases: HashMap<u32, (i32, i32)> = HashMap::new();
+    for result in results {
+        let bases = strand_bases.entry(result.query_id).or_default();
+        match result.strand {
+            Strand::Forward => bases.0 += result.target_end - result.target_start,
+            Strand::Reverse => bases.1 += result.target_end - result.target_start,
+        }
+    }
+    for result in results {
+        let (forward, reverse) = strand_bases[&result.query_id];
+        let minority = match result.strand {
+            Strand::Forward => forward < reverse,
+            Strand::Reverse => reverse < forward,
+        };
+        if minority && result.target_end - result.target_start >= min_size {
+            variants.push(StructuralVariant {
+                sv_type: SvType::Inversion,
+                target_start: result.target_start,
+                target_end: result.target_end,
+                query_id: result.query_id,
+                query_start: result.query_start,
+                query_end: result.query_end,
+                strand: result.strand,
+            });
+        }
+    }
+
+    let mut by_haplotype: BTreeMap<&str, Vec<&ProjectedInterval>> = BTreeMap::new();
+    for &result in results {
+        let query_name = impg.seq_index.get_name(result.query_id).unwrap();
+        by_haplotype.entry(pansn_haplotype(query_name)).or_default().push(result);
+    }
+    for projections in by_haplotype.values_mut() {
+        projections.sort_by_key(|r| (r.target_start, r.target_end));
+        for pair in projections.windows(2) {
+            let (previous, next) = (pair[0], pair[1]);
+            if previous.query_id != next.query_id && next.target_start >= previous.target_end {
+                let breakpoint = match next.strand {
+                    Strand::Forward => next.query_start,
+                    Strand::Reverse => next.query_end,
+                };
+                variants.push(StructuralVariant {
+                    sv_type: SvType::Translocation,
+                    target_start: previous.target_end,
+                    target_end: next.target_start,
+                    query_id: next.query_id,
+                    query_start: breakpoint,
+                    query_end: breakpoint,
+                    strand: next.strand,
+                });
+            }
+        }
+    }
+    variants
+}
+
+/// Structural variants of the results against the target, sorted by target position.
+/// The projection of the input range onto itself is ignored.
+pub fn detect_structural_variants(impg: &Impg, results: &[ProjectedInterval], min_size: i32) -> Vec<StructuralVariant> {
+    let projections: Vec<&ProjectedInterval> = results.iter()
+        .filter(|result| result.provenance.alignment_id.is_some())
+        .collect();
+    let mut variants: Vec<StructuralVariant> = projections.iter()
+        .flat_map(|result| cigar_indels(result, min_size))
+        .collect();
+    variants.extend(alignment_breaks(impg, &projections, min_size));
+    variants.sort_by_key(|v| (v.event_key(), v.query_id));
+    variants
+}
+
+/// Haplotypes supporting each event
+fn supporting_haplotypes<'a>(impg: &'a Impg, variants: &[StructuralVariant]) -> HashMap<(i32, i32, SvType, i32), Vec<&'a str>> {
+    let mut supporters: HashMap<_, Vec<&str>> = HashMap::new();
+    for variant in variants {
+        let haplotype = pansn_haplotype(impg.seq_index.get_name(variant.query_id).unwrap());
+        let haplotypes = supporters.entry(variant.event_key()).or_default();
+        if !haplotypes.contains(&haplotype) {
+            haplotypes.push(haplotype);
+        }
+    }
+    supporters
+}
+
+/// Write one BEDPE line per variant and genome (query range, target range, type, number of
+/// supporting haplotypes, strands and the supporting haplotypes)
+pub fn write_bedpe<W: Write>(writer: &mut W, impg: &Impg, target_name: &str, variants: &[StructuralVariant]) -> io::Result<()> {
+    let supporters = supporting_haplotypes(impg, variants);
+    for variant in variants {
+        let haplotypes = &supporters[&variant.event_key()];
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t+\t{}",
+            impg.seq_index.get_name(variant.query_id).unwrap(), variant.query_start, variant.query_end,
+            target_name, variant.target_start, variant.target_end,
+            variant.sv_type, haplotypes.len(),
+            if variant.strand == Strand::Forward { '+' } else { '-' },
+            haplotypes.join(","),
+        )?;
+    }
+    Ok(())
+}
+
+/// Write one symbolic-allele VCF record per event, genotyping the `haplotypes` columns as
+/// supporting (`1`), having a projection over the event without it (`0`) or missing (`.`)
+pub fn write_vcf<W: Write>(writer: &mut W, impg: &Impg, target_name: &str, variants: &[StructuralVariant], results: &[ProjectedInterval], haplotypes: &[&str]) -> io::Result<()> {
+    let supporters = supporting_haplotypes(impg, variants);
+    let mut written = Vec::new();
+    for variant in variants {
+        let key = variant.event_key();
+        if written.contains(&key) {
+            continue;
+        }
+        written.push(key);
+        let event_haplotypes = &supporters[&key];
+
+        // VCF positions are 1-based and symbolic alleles start after their padding base
+        let pos = variant.target_start.max(1);
+        let mut info = format!("SVTYPE={};END={}", variant.sv_type, variant.target_end.max(pos));
+        match variant.sv_type {
+            SvType::Deletion => info.push_str(&format!(";SVLEN=-{}", variant.len())),
+            SvType::Insertion | SvType::Inversion => info.push_str(&format!(";SVLEN={}", variant.len())),
+            SvType::Translocation => info.push_str(&format!(
+                ";CHR2={};POS2={}", impg.seq_index.get_name(variant.query_id).unwrap(), variant.query_start + 1
+            )),
+        }
+        info.push_str(&format!(";SUPP={}", event_haplotypes.len()));
+        write!(writer, "{}\t{}\t.\tN\t<{}>\t.\t.\t{}\tGT", target_name, pos, variant.sv_type, info)?;
+
+        let event_end = variant.target_end.max(variant.target_start + 1);
+        for haplotype in haplotypes {
+            let genotype = if event_haplotypes.contains(haplotype) {
+                "1"
+            } else if results.iter().any(|r| {
+                r.provenance.alignment_id.is_some()
+                    && r.target_start < event_end && variant.target_start < r.target_end
+                    && pansn_haplotype(impg.seq_index.get_name(r.query_id).unwrap()) == *haplotype
+            }) {
+                "0"
+            } else {
+                "."
+            };
+            write!(writer, "\t{}", genotype)?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impg::{CigarOp, Provenance};
+    use crate::paf::parse_paf;
+    use std::io::BufReader;
+
+    fn result(query_start: i32, query_end: i32, target_start: i32, target_end: i32, strand: Strand, cigar: &[(i32, char)]) -> ProjectedInterval {
+        ProjectedInterval {
+            query_start,
+            query_end,
+            target_start,
+            target_end,
+            strand,
+            cigar: cigar.iter().map(|&(len, op)| CigarOp::new(len, op)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cigar_indels_forward() {
+        let r = result(0, 160, 100, 300, Strand::Forward, &[(50, '='), (60, 'D'), (10, 'I'), (40, '='), (50, 'I'), (50, '=')]);
+        let variants = cigar_indels(&r, 50);
+        assert_eq!(variants.len(), 2);
+        assert_eq!((variants[0].sv_type, variants[0].target_start, variants[0].target_end), (SvType::Deletion, 150, 210));
+        assert_eq!((variants[0].query_start, variants[0].query_end), (50, 50));
+        assert_eq!((variants[1].sv_type, variants[1].target_start, variants[1].target_end), (SvType::Insertion, 250, 250));
+        assert_eq!((variants[1].query_start, variants[1].query_end), (100, 150));
+        assert_eq!(variants[1].len(), 50);
+    }
+
+    #[test]
+    fn test_cigar_indels_reverse() {
+        let r = result(1000, 1150, 0, 100, Strand::Reverse, &[(50, '='), (50, 'I'), (50, '=')]);
+        let variants = cigar_indels(&r, 50);
+        assert_eq!(variants.len(), 1);
+        assert_eq!((variants[0].target_start, variants[0].target_end), (50, 50));
+        assert_eq!((variants[0].query_start, variants[0].query_end), (1050, 1100));
+    }
+
+    /// An index of the sequences s1#1#a, s1#1#b and s2#1#c against T (alignments are not read)
+    fn test_impg() -> Impg {
+        let paf_data = "s1#1#a\t200\t0\t200\t+\tT\t400\t0\t200\t200\t200\t60\tcg:Z:200=\n\
+                        s1#1#b\t1000\t500\t600\t+\tT\t400\t300\t400\t100\t100\t60\tcg:Z:100=\n\
+                        s2#1#c\t340\t0\t340\t+\tT\t400\t0\t400\t340\t400\t60\tcg:Z:200=60D140=\n";
+        let records = parse_paf(BufReader::new(paf_data.as_bytes())).unwrap();
+        Impg::from_paf_records(&records, "sv_test.paf").unwrap()
+    }
+
+    fn projection(impg: &Impg, query: (&str, i32, i32), target: (i32, i32), strand: Strand, cigar: &[(i32, char)]) -> ProjectedInterval {
+        ProjectedInterval {
+            query_id: impg.seq_index.get_id(query.0).unwrap(),
+            provenance: Provenance { alignment_id: Some(0), ..Default::default() },
+            ..result(query.1, query.2, target.0, target.1, strand, cigar)
+        }
+    }
+
+    /// s1#1 has an inversion at 100-160 and continues on another sequence after a gap at 200-300,
+    /// s2#1 has a deletion at 200-260
+    fn test_results(impg: &Impg) -> Vec<ProjectedInterval> {
+        vec![
+            projection(impg, ("s1#1#a", 0, 100), (0, 100), Strand::Forward, &[(100, '=')]),
+            projection(impg, ("s1#1#a", 100, 160), (100, 160), Strand::Reverse, &[(60, '=')]),
+            projection(impg, ("s1#1#a", 160, 200), (160, 200), Strand::Forward, &[(40, '=')]),
+            projection(impg, ("s1#1#b", 500, 600), (300, 400), Strand::Forward, &[(100, '=')]),
+            projection(impg, ("s2#1#c", 0, 340), (0, 400), Strand::Forward, &[(200, '='), (60, 'D'), (140, '=')]),
+        ]
+    }
+
+    #[test]
+    fn test_alignment_breaks() {
+        let impg = test_impg();
+        let results = test_results(&impg);
+        let projections: Vec<&ProjectedInterval> = results.iter().collect();
+        let (a, b) = (impg.seq_index.get_id("s1#1#a").unwrap(), impg.seq_index.get_id("s1#1#b").unwrap());
+        assert_eq!(alignment_breaks(&impg, &projections, 50), vec![
+            StructuralVariant {
+                sv_type: SvType::Inversion, target_start: 100, target_end: 160,
+                query_id: a, query_start: 100, query_end: 160, strand: Strand::Reverse,
+            },
+            StructuralVariant {
+                sv_type: SvType::Translocation, target_start: 200, target_end: 300,
+                query_id: b, query_start: 500, query_end: 500, strand: Strand::Forward,
+            },
+        ]);
+        // Inversions shorter than the minimum size are not reported
+        assert_eq!(alignment_breaks(&impg, &projections, 61).len(), 1);
+    }
+
+    #[test]
+    fn test_write_vcf() {
+        let impg = test_impg();
+        let results = test_results(&impg);
+        let variants = detect_structural_variants(&impg, &results, 50);
+        let mut out = Vec::new();
+        write_vcf(&mut out, &impg, "T", &variants, &results, &["s1#1", "s2#1", "s3#1"]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+            T\t100\t.\tN\t<INV>\t.\t.\tSVTYPE=INV;END=160;SVLEN=60;SUPP=1\tGT\t1\t0\t.\n\
+            T\t200\t.\tN\t<DEL>\t.\t.\tSVTYPE=DEL;END=260;SVLEN=-60;SUPP=1\tGT\t.\t1\t.\n\
+            T\t200\t.\tN\t<TRA>\t.\t.\tSVTYPE=TRA;END=300;CHR2=s1#1#b;POS2=501;SUPP=1\tGT\t1\t0\t.\n");
+    }
+}
@@ -0,0 +1,47 @@
+use std::io::BufReader;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use crate::impg::Impg;
+use crate::paf::parse_paf;
+
+/// A file in the temporary directory, removed when dropped
+pub(crate) struct TempFile(PathBuf);
+
+impl TempFile {
+    pub(crate) fn new(name: &str, contents: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("impg_test_{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        TempFile(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// An index of PAF records written to a temporary file, removed when dropped
+pub(crate) struct TestImpg {
+    impg: Impg,
+    _paf_file: TempFile,
+}
+
+impl Deref for TestImpg {
+    type Target = Impg;
+
+    fn deref(&self) -> &Impg {
+        &self.impg
+    }
+}
+
+pub(crate) fn test_impg(name: &str, paf_data: &str) -> TestImpg {
+    let paf_file = TempFile::new(&format!("{}.paf", name), paf_data);
+    let records = parse_paf(BufReader::new(paf_data.as_bytes())).unwrap();
+    let impg = Impg::from_paf_records(&records, paf_file.path().to_str().unwrap()).unwrap();
+    TestImpg { impg, _paf_file: paf_file }
+}
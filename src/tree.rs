@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use crate::impg::{Impg, ProjectedInterval};
+use crate::seqidx::pansn_haplotype;
+use crate::similarity::IdentityMatrix;
+
+/// Collapse an identity matrix between results into a distance matrix between their PanSN haplotypes,
+/// using the most similar pair of results of each pair of haplotypes (distance 1 without overlap)
+pub fn haplotype_distances(impg: &Impg, results: &[ProjectedInterval], matrix: &IdentityMatrix) -> (Vec<String>, Vec<Vec<f64>>) {
+    let mut haplotypes: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, result) in results.iter().enumerate() {
+        haplotypes.entry(pansn_haplotype(impg.seq_index.get_name(result.query_id).unwrap())).or_default().push(i);
+    }
+    let groups: Vec<&Vec<usize>> = haplotypes.values().collect();
+    let distances = groups.iter().enumerate().map(|(a, rows)| {
+        groups.iter().enumerate().map(|(b, columns)| {
+            if a == b {
+                return 0.0;
+            }
+            let identity = rows.iter()
+                .flat_map(|&i| columns.iter().filter_map(move |&j| matrix[i][j]))
+                .fold(0.0, f64::max);
+            1.0 - identity
+        }).collect()
+    }).collect();
+    (haplotypes.keys().map(|h| h.to_string()).collect(), distances)
+}
+
+/// Build a neighbor-joining tree from a distance matrix and return it in Newick format (unrooted,
+/// with a trifurcation at the top). Negative branch lengths are set to 0.
+pub fn neighbor_joining(labels: &[String], distances: &[Vec<f64>]) -> String {
+    let mut nodes: Vec<String> = labels.to_vec();
+    let mut d: Vec<Vec<f64>> = distances.to_vec();
+    let branch = |node: &str, length: f64| format!("{}:{:.6}", node, length.max(0.0));
+
+    while nodes.len() > 3 {
+        let n = nodes.len();
+        let sums: Vec<f64> = d.iter().map(|row| row.iter().sum()).collect();
+        let mut best = (0, 1);
+        let mut best_q = f64::INFINITY;
+        for i in 0..n {
+            for j in i + 1..n {
+                let q = (n - 2) as f64 * d[i][j] - sums[i] - sums[j];
+                if q < best_q {
+                    best_q = q;
+                    best = (i, j);
+                }
+            }
+        }
+        let (i, j) = best;
+        let length_i = d[i][j] / 2.0 + (sums[i] - sums[j]) / (2.0 * (n - 2) as f64);
+        let length_j = d[i][j] - length_i;
+        let joined = format!("({},{})", branch(&nodes[i], length_i), branch(&nodes[j], length_j));
+        let joined_distances: Vec<f64> = (0..n)
+            .filter(|&k| k != i && k != j)
+            .map(|k| (d[i][k] + d[j][k] - d[i][j]) / 2.0)
+            .collect();
+
+        // Remove i and j (j > i), then add the joined node last
+        for row in d.iter_mut() {
+            row.remove(j);
+            row.remove(i);
+        }
+        d.remove(j);
+        d.remove(i);
+        nodes.remove(j);
+        nodes.remove(i);
+        for (row, distance) in d.iter_mut().zip(&joined_distances) {
+            row.push(*distance);
+        }
+        let mut joined_row = joined_distances;
+        joined_row.push(0.0);
+        d.push(joined_row);
+        nodes.push(joined);
+    }
+
+    match nodes.len() {
+        0 => ";".to_string(),
+        1 => format!("{};", nodes[0]),
+        2 => format!("({},{});", branch(&nodes[0], d[0][1] / 2.0), branch(&nodes[1], d[0][1] / 2.0)),
+        _ => {
+            let (ab, ac, bc) = (d[0][1], d[0][2], d[1][2]);
+            format!(
+                "({},{},{});",
+                branch(&nodes[0], (ab + ac - bc) / 2.0),
+                branch(&nodes[1], (ab + bc - ac) / 2.0),
+                branch(&nodes[2], (ac + bc - ab) / 2.0)
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neighbor_joining_additive() {
+        // ((A:1,B:2):1,C:1,D:1)
+        let labels: Vec<String> = ["A", "B", "C", "D"].iter().map(|s| s.to_string()).collect();
+        let distances = vec![
+            vec![0.0, 3.0, 3.0, 3.0],
+            vec![3.0, 0.0, 4.0, 4.0],
+            vec![3.0, 4.0, 0.0, 2.0],
+            vec![3.0, 4.0, 2.0, 0.0],
+        ];
+        assert_eq!(
+            neighbor_joining(&labels, &distances),
+            "(C:1.000000,D:1.000000,(A:1.000000,B:2.000000):1.000000);"
+        );
+    }
+
+    #[test]
+    fn test_neighbor_joining_small() {
+        let labels: Vec<String> = ["A", "B"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(neighbor_joining(&labels, &[vec![0.0, 0.5], vec![0.5, 0.0]]), "(A:0.250000,B:0.250000);");
+        assert_eq!(neighbor_joining(&labels[..1], &[vec![0.0]]), "A;");
+    }
+}
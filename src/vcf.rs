@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use crate::faidx::FastaIndex;
+use crate::impg::{Impg, ProjectedInterval};
+use crate::paf::Strand;
+use crate::seqidx::pansn_haplotype;
+
+/// A difference between a query and the target, VCF-style: indels include the preceding target base
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    /// 0-based target position of the first reference base
+    pub pos: i32,
+    pub ref_allele: Vec<u8>,
+    pub alt_allele: Vec<u8>,
+}
+
+/// Variants encoded by the CIGAR of a projected interval.
+///
+/// `target_seq` is the target sequence starting at `target_offset`, which should include the base
+/// before the interval so that indels at its start can be anchored. `query_seq` is the query range
+/// in the orientation of the target. Indels without an anchor base are skipped.
+pub fn call_variants(result: &ProjectedInterval, target_seq: &[u8], target_offset: i32, query_seq: &[u8]) -> Vec<Variant> {
+    let target_base = |t: i32| {
+        usize::try_from(t - target_offset).ok()
+            .and_then(|i| target_seq.get(i))
+            .map(|b| b.to_ascii_uppercase())
+    };
+    let query_bases = |q: usize, len: usize| query_seq.get(q..q + len).map(|s| s.to_ascii_uppercase());
+
+    let mut variants = Vec::new();
+    let mut t = result.target_start;
+    let mut q = 0;
+    for op in &result.cigar {
+        let len = op.len();
+        match op.op() {
+            'X' | 'M' => {
+                for k in 0..len {
+                    if let (Some(ref_base), Some(alt)) = (target_base(t + k), query_bases(q + k as usize, 1)) {
+                        if ref_base != alt[0] {
+                            variants.push(Variant { pos: t + k, ref_allele: vec![ref_base], alt_allele: alt });
+                        }
+                    }
+                }
+            }
+            'I' => {
+                if let (Some(anchor), Some(inserted)) = (target_base(t - 1), query_bases(q, len as usize)) {
+                    let mut alt_allele = vec![anchor];
+                    alt_allele.extend(inserted);
+                    variants.push(Variant { pos: t - 1, ref_allele: vec![anchor], alt_allele });
+                }
+            }
+            'D' => {
+                if let Some(anchor) = target_base(t - 1) {
+                    let mut ref_allele = vec![anchor];
+                    ref_allele.extend((t..t + len).filter_map(target_base));
+                    variants.push(Variant { pos: t - 1, ref_allele, alt_allele: vec![anchor] });
+                }
+            }
+            _ => {}
+        }
+        t += op.target_delta();
+        q += op.query_delta(Strand::Forward) as usize;
+    }
+    variants
+}
+
+/// Merge the variants of one projection that overlap at the same site (like an SNV and an indel
+/// anchored on the same base) into a single haplotype allele. `variants` must be in the order of
+/// `call_variants`.
+pub fn merge_variants(variants: Vec<Variant>) -> Vec<Variant> {
+    // Each variant as the target range it replaces and the query bases that replace it
+    let edit = |v: &Variant| match (v.ref_allele.len(), v.alt_allele.len()) {
+        (1, 1) => (v.pos, v.pos + 1, v.alt_allele.clone()),
+        (ref_len, _) => (v.pos + 1, v.pos + ref_len as i32, v.alt_allele[1..].to_vec()),
+    };
+
+    let mut merged = Vec::new();
+    let mut current: Option<Variant> = None;
+    let mut edits: Vec<(i32, i32, Vec<u8>)> = Vec::new();
+    for variant in variants {
+        match current {
+            Some(ref mut site) if variant.pos < site.pos + site.ref_allele.len() as i32 => {
+                let offset = (variant.pos - site.pos) as usize;
+                if offset + variant.ref_allele.len() > site.ref_allele.len() {
+                    site.ref_allele.extend_from_slice(&variant.ref_allele[site.ref_allele.len() - offset..]);
+                }
+            }
+            _ => {
+                merged.extend(apply_edits(current.take(), &mut edits));
+                current = Some(Variant { alt_allele: Vec::new(), ..variant.clone() });
+            }
+        }
+        edits.push(edit(&variant));
+    }
+    merged.extend(apply_edits(current, &mut edits));
+    merged
+}
+
+/// Build the alternate allele of a merged site from the edits of its variants
+fn apply_edits(site: Option<Variant>, edits: &mut Vec<(i32, i32, Vec<u8>)>) -> Option<Variant> {
+    let mut site = site?;
+    let mut cursor = site.pos;
+    for (start, end, bases) in edits.drain(..) {
+        site.alt_allele.extend_from_slice(&site.ref_allele[(cursor - site.pos) as usize..(start - site.pos) as usize]);
+        site.alt_allele.extend(bases);
+        cursor = end;
+    }
+    site.alt_allele.extend_from_slice(&site.ref_allele[(cursor - site.pos) as usize..]);
+    Some(site)
+}
+
+/// Write a VCF header with a genotype column per haplotype; `meta` holds extra `##` lines (without the `##`)
+pub fn write_vcf_header<W: Write>(writer: &mut W, contigs: &[(&str, usize)], meta: &[&str], haplotypes: &[&str]) -> io::Result<()> {
+    writeln!(writer, "##fileformat=VCFv4.2")?;
+    writeln!(writer, "##source=impg")?;
+    for (name, length) in contigs {
+        writeln!(writer, "##contig=<ID={},length={}>", name, length)?;
+    }
+    for line in meta {
+        writeln!(writer, "##{}", line)?;
+    }
+    writeln!(writer, "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">")?;
+    write!(writer, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT")?;
+    for haplotype in haplotypes {
+        write!(writer, "\t{}", haplotype)?;
+    }
+    writeln!(writer)
+}
+
+/// Write the variants of the results against the target as VCF records with one haploid genotype
+/// column per PanSN haplotype in `haplotypes`; results of other haplotypes are ignored.
+///
+/// A haplotype is genotyped as reference where it has an aligned base at the record position,
+/// and as missing where it has no projection (or a deletion) there. The variants of a projection at
+/// the same site are merged into one allele; when a haplotype has several projections over a
+/// position, the first one carrying a variant there wins.
+pub fn write_vcf_records<W: Write>(writer: &mut W, impg: &Impg, target_id: u32, results: &[ProjectedInterval], fasta_index: &FastaIndex, haplotypes: &[&str]) -> io::Result<()> {
+    let (range_start, range_end) = match (results.iter().map(|r| r.target_start).min(), results.iter().map(|r| r.target_end).max()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Ok(()),
+    };
+    let target_name = impg.seq_index.get_name(target_id).unwrap();
+    let target_offset = (range_start - 1).max(0);
+    let target_seq = fasta_index.fetch(target_name, target_offset, range_end)?;
+
+    let columns: HashMap<&str, usize> = haplotypes.iter().enumerate().map(|(i, &h)| (h, i)).collect();
+    let mut covered: Vec<Vec<(i32, i32)>> = vec![Vec::new(); haplotypes.len()];
+    let mut calls: Vec<HashMap<i32, Variant>> = vec![HashMap::new(); haplotypes.len()];
+    let mut sites: BTreeMap<i32, Vec<Variant>> = BTreeMap::new();
+    for result in results {
+        let query_name = impg.seq_index.get_name(result.query_id).unwrap();
+        let column = match columns.get(pansn_haplotype(query_name)) {
+            Some(&column) if result.query_start < result.query_end => column,
+            _ => continue,
+        };
+        let query_seq = fasta_index.fetch_oriented(query_name, result.query_start, result.query_end, result.strand)?;
+        for variant in merge_variants(call_variants(result, &target_seq, target_offset, &query_seq)) {
+            let site = sites.entry(variant.pos).or_default();
+            if !site.contains(&variant) {
+                site.push(variant.clone());
+            }
+            calls[column].entry(variant.pos).or_insert(variant);
+        }
+        covered[column].extend(result.aligned_target_ranges());
+    }
+
+    for (pos, variants) in sites {
+        // Variants starting at the same position share the longest reference allele
+        let ref_allele = variants.iter().map(|v| &v.ref_allele).max_by_key(|r| r.len()).unwrap().clone();
+        let normalize = |v: &Variant| {
+            let mut alt_allele = v.alt_allele.clone();
+            alt_allele.extend_from_slice(&ref_allele[v.ref_allele.len()..]);
+            alt_allele
+        };
+        let mut alt_alleles: Vec<Vec<u8>> = Vec::new();
+        for variant in &variants {
+            let alt_allele = normalize(variant);
+            if !alt_alleles.contains(&alt_allele) {
+                alt_alleles.push(alt_allele);
+            }
+        }
+
+        let alts: Vec<String> = alt_alleles.iter().map(|a| String::from_utf8_lossy(a).into_owned()).collect();
+        write!(writer, "{}\t{}\t.\t{}\t{}\t.\t.\t.\tGT", target_name, pos + 1, String::from_utf8_lossy(&ref_allele), alts.join(","))?;
+        for column in 0..haplotypes.len() {
+            match calls[column].get(&pos) {
+                Some(variant) => {
+                    let allele = alt_alleles.iter().position(|a| *a == normalize(variant)).unwrap() + 1;
+                    write!(writer, "\t{}", allele)?;
+                }
+                None if covered[column].iter().any(|&(start, end)| start <= pos && pos < end) => write!(writer, "\t0")?,
+                None => write!(writer, "\t.")?,
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impg::CigarOp;
+    use crate::test_util::{test_impg, TempFile};
+
+    fn result(target_start: i32, target_end: i32, cigar: &[(i32, char)]) -> ProjectedInterval {
+        ProjectedInterval {
+            target_start,
+            target_end,
+            cigar: cigar.iter().map(|&(len, op)| CigarOp::new(len, op)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn variant(pos: i32, ref_allele: &str, alt_allele: &str) -> Variant {
+        Variant { pos, ref_allele: ref_allele.as_bytes().to_vec(), alt_allele: alt_allele.as_bytes().to_vec() }
+    }
+
+    #[test]
+    fn test_call_variants() {
+        // Target sequence starts at position 9, one base before the interval
+        let target = b"TACGTACGTAC";
+        let r = result(10, 20, &[(2, '='), (1, 'X'), (2, 'I'), (2, 'D'), (3, 'M'), (2, '=')]);
+        assert_eq!(
+            call_variants(&r, target, 9, b"acTggCATAC"),
+            vec![variant(12, "G", "T"), variant(12, "G", "GGG"), variant(12, "GTA", "G"), variant(16, "G", "A")]
+        );
+    }
+
+    #[test]
+    fn test_merge_variants() {
+        // An SNV, an insertion and a deletion anchored on the same base form one allele
+        let variants = vec![variant(12, "G", "T"), variant(12, "G", "GGG"), variant(12, "GTA", "G"), variant(16, "G", "A")];
+        assert_eq!(merge_variants(variants), vec![variant(12, "GTA", "TGG"), variant(16, "G", "A")]);
+
+        // An SNV followed by an insertion after it
+        let variants = vec![variant(5, "C", "A"), variant(5, "C", "CTT"), variant(6, "G", "T")];
+        assert_eq!(merge_variants(variants), vec![variant(5, "C", "ATT"), variant(6, "G", "T")]);
+
+        // A deletion anchored on an SNV
+        let variants = vec![variant(3, "A", "G"), variant(3, "ACC", "A")];
+        assert_eq!(merge_variants(variants), vec![variant(3, "ACC", "G")]);
+    }
+
+    #[test]
+    fn test_call_variants_unanchored() {
+        let r = result(0, 4, &[(1, 'I'), (1, 'D'), (3, '=')]);
+        assert!(call_variants(&r, b"ACGT", 0, b"TCGT").is_empty());
+    }
+
+    #[test]
+    fn test_write_vcf_records_same_site() {
+        // The haplotype has an SNV at position 4 of T and an insertion anchored on it
+        let impg = test_impg("vcf", "s1#1#q\t12\t0\t12\t+\tT\t10\t0\t10\t9\t12\t60\tcg:Z:4=1X2I5=\n");
+        let fasta_file = TempFile::new("vcf.fa", ">T\nAACCGGTTAA\n>s1#1#q\nAACCTCCGTTAA\n");
+        let _fai_file = TempFile::new("vcf.fa.fai", "T\t10\t3\t10\t11\ns1#1#q\t12\t22\t12\t13\n");
+        let fasta_index = FastaIndex::from_paths(&[fasta_file.path().to_str().unwrap().to_string()]).unwrap();
+        let target_id = impg.seq_index.get_id("T").unwrap();
+        let results = impg.project(target_id, 0, 10, None).unwrap();
+        let mut out = Vec::new();
+        write_vcf_records(&mut out, &impg, target_id, &results, &fasta_index, &["s1#1"]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "T\t5\t.\tG\tTCC\t.\t.\t.\tGT\t1\n");
+    }
+}